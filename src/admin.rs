@@ -0,0 +1,326 @@
+// 管理接口 - 本地HTTP服务，以JSON形式提供规则、目标、健康状态和转发统计
+use anyhow::Result;
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::common::{CommonManager, TargetInfo};
+use crate::config::AdminConfig;
+use crate::forwarder::{ForwarderMap, SmartForwarder};
+use crate::metrics;
+
+// 读取HTTP请求行：请求可能分多次到达，读到第一个换行或缓冲区满为止，连接直接关闭时返回None
+async fn read_request_line<R: AsyncRead + Unpin>(stream: &mut R) -> Result<Option<String>> {
+    let mut buffer = vec![0u8; 8192];
+    let mut len = 0;
+    while len < buffer.len() {
+        let n = stream.read(&mut buffer[len..]).await?;
+        if n == 0 {
+            break;
+        }
+        len += n;
+        if buffer[..len].contains(&b'\n') {
+            break;
+        }
+    }
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let request = String::from_utf8_lossy(&buffer[..len]);
+    Ok(Some(request.lines().next().unwrap_or("").to_string()))
+}
+
+// HTTP响应
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, value: Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json; charset=utf-8",
+            body: serde_json::to_string_pretty(&value).unwrap_or_default(),
+        }
+    }
+
+    fn text(status: u16, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            body,
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, json!({ "error": message }))
+    }
+
+    fn status_text(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+pub struct AdminServer {
+    listen: String,
+    common_manager: CommonManager,
+    forwarders: ForwarderMap,
+}
+
+impl AdminServer {
+    pub fn new(
+        config: &AdminConfig,
+        common_manager: CommonManager,
+        forwarders: ForwarderMap,
+    ) -> Self {
+        Self {
+            listen: config.listen.clone(),
+            common_manager,
+            forwarders,
+        }
+    }
+
+    pub async fn start(self) -> Result<()> {
+        let listener = TcpListener::bind(&self.listen)
+            .await
+            .map_err(|e| anyhow::anyhow!("管理接口绑定失败 {}: {}", self.listen, e))?;
+        info!(
+            "管理接口已启动: http://{}/api/rules, 指标: http://{}/metrics",
+            self.listen, self.listen
+        );
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let common_manager = self.common_manager.clone();
+                        let forwarders = self.forwarders.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) =
+                                Self::handle_request(stream, &common_manager, &forwarders).await
+                            {
+                                debug!("管理接口请求处理失败: {e}");
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        warn!("管理接口接受连接失败: {e}");
+                        tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn handle_request(
+        mut stream: TcpStream,
+        common_manager: &CommonManager,
+        forwarders: &ForwarderMap,
+    ) -> Result<()> {
+        // 只需要请求行：方法和路径
+        let Some(request_line) = read_request_line(&mut stream).await? else {
+            return Ok(());
+        };
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or("");
+        let path = parts.next().unwrap_or("/");
+        let path = path.split('?').next().unwrap_or(path);
+
+        let response = Self::route(method, path, common_manager, forwarders).await;
+        debug!("管理接口: {} {} -> {}", method, path, response.status);
+
+        let head = format!(
+            "HTTP/1.1 {} {}\r\n\
+             Content-Type: {}\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\
+             \r\n",
+            response.status,
+            response.status_text(),
+            response.content_type,
+            response.body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(response.body.as_bytes()).await?;
+        Ok(())
+    }
+
+    async fn route(
+        method: &str,
+        path: &str,
+        common_manager: &CommonManager,
+        forwarders: &ForwarderMap,
+    ) -> Response {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (method, segments.as_slice()) {
+            ("GET", ["api", "rules"]) => {
+                Response::json(200, Self::rules_json(common_manager, None).await)
+            }
+            ("GET", ["api", "rules", name]) => {
+                let rules = Self::rules_json(common_manager, Some(name)).await;
+                match rules.as_array().and_then(|rules| rules.first()) {
+                    Some(rule) => Response::json(200, rule.clone()),
+                    None => Response::error(404, &format!("规则不存在: {name}")),
+                }
+            }
+            ("GET", ["api", "targets"]) => {
+                let targets: Vec<Value> = common_manager
+                    .target_snapshots()
+                    .iter()
+                    .map(target_json)
+                    .collect();
+                Response::json(200, Value::Array(targets))
+            }
+            ("GET", ["metrics"]) => {
+                Response::text(200, metrics::render(common_manager, forwarders).await)
+            }
+            ("GET", ["api", "stats"]) => {
+                let stats = SmartForwarder::get_stats(forwarders).await;
+                Response::json(200, json!(stats))
+            }
+            ("POST", ["api", "rules", name, "health-check"]) => {
+                match common_manager.check_rule_now(name).await {
+                    Ok(result) => Response::json(
+                        200,
+                        json!({
+                            "result": result,
+                            "rule": Self::rules_json(common_manager, Some(name)).await[0],
+                        }),
+                    ),
+                    Err(e) => Response::error(404, &e.to_string()),
+                }
+            }
+            ("POST", ["api", "rules", name, "resolve"]) => {
+                match common_manager.resolve_rule_now(name).await {
+                    Ok(()) => Response::json(
+                        200,
+                        json!({
+                            "result": "ok",
+                            "rule": Self::rules_json(common_manager, Some(name)).await[0],
+                        }),
+                    ),
+                    Err(e) => Response::error(404, &e.to_string()),
+                }
+            }
+            // 接口存在但请求方法不匹配时返回405
+            (_, segments) => match Self::route_method(segments) {
+                Some(allowed) => Response::error(
+                    405,
+                    &format!("不支持的请求方法: {method}，该接口只支持 {allowed}"),
+                ),
+                None => Response::error(404, &format!("未知接口: {method} {path}")),
+            },
+        }
+    }
+
+    // 按路径判断接口是否存在及其支持的请求方法
+    fn route_method(segments: &[&str]) -> Option<&'static str> {
+        match segments {
+            ["api", "rules"]
+            | ["api", "rules", _]
+            | ["api", "targets"]
+            | ["api", "stats"]
+            | ["metrics"] => Some("GET"),
+            ["api", "rules", _, "health-check" | "resolve"] => Some("POST"),
+            _ => None,
+        }
+    }
+
+    // 规则列表：配置信息 + 当前选中目标 + 所有目标状态
+    async fn rules_json(common_manager: &CommonManager, only: Option<&str>) -> Value {
+        let config = common_manager.config().await;
+        let snapshots = common_manager.rule_snapshots().await;
+
+        let rules: Vec<Value> = config
+            .rules
+            .iter()
+            .filter(|rule| only.is_none_or(|name| rule.name == name))
+            .map(|rule| {
+                let snapshot = snapshots.iter().find(|s| s.name == rule.name);
+                json!({
+                    "name": rule.name,
+                    "listen_port": rule.listen_port,
+                    "protocols": rule.get_protocols(),
+                    "strategy": rule.get_strategy().as_str(),
+                    "configured_targets": rule.targets,
+                    "selected_target": snapshot
+                        .and_then(|s| s.selected_target.as_ref())
+                        .map(target_json),
+                    "targets": snapshot
+                        .map(|s| s.targets.iter().map(target_json).collect::<Vec<_>>())
+                        .unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        Value::Array(rules)
+    }
+}
+
+fn target_json(target: &TargetInfo) -> Value {
+    json!({
+        "original": target.original,
+        "resolved": target.resolved.to_string(),
+        "healthy": target.healthy,
+        "fail_count": target.fail_count,
+        "success_count": target.success_count,
+        "weight": target.weight,
+        "last_check_secs_ago": target.last_check.elapsed().as_secs(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_request_line() {
+        // 请求行分多次到达
+        let mut stream = tokio_test::io::Builder::new()
+            .read(b"GET /api")
+            .read(b"/rules HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .build();
+        let line = read_request_line(&mut stream).await.unwrap();
+        assert_eq!(line.as_deref(), Some("GET /api/rules HTTP/1.1"));
+
+        let mut stream = tokio_test::io::Builder::new().build();
+        assert!(read_request_line(&mut stream).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_route_method_not_allowed() {
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [127.0.0.1]}\n\
+                    rules: [{name: r, listen_port: 80, targets: [\"10.0.0.1:80\"]}]";
+        let manager = CommonManager::new(serde_yml::from_str(yaml).unwrap());
+        let forwarders: ForwarderMap = Default::default();
+        let status = |method: &'static str, path: &'static str| {
+            let manager = &manager;
+            let forwarders = &forwarders;
+            async move {
+                AdminServer::route(method, path, manager, forwarders)
+                    .await
+                    .status
+            }
+        };
+
+        assert_eq!(status("GET", "/api/rules").await, 200);
+        // 路径存在但方法不对时返回405，而不是404
+        assert_eq!(status("POST", "/api/rules").await, 405);
+        assert_eq!(status("GET", "/api/rules/r/health-check").await, 405);
+        assert_eq!(status("DELETE", "/metrics").await, 405);
+        assert_eq!(status("GET", "/api/unknown").await, 404);
+    }
+}
@@ -1,9 +1,15 @@
-use crate::config::Config;
-use crate::utils::resolve_target;
+use crate::config::{Config, HealthCheckConfig, LoadBalanceStrategy, RuleDiff};
+use crate::dns::{DnsResolvers, DnsStats};
+use crate::utils::{resolve_target, target_hostname, ResolvedAddr};
 use anyhow::Result;
 use dashmap::DashMap;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
@@ -11,6 +17,11 @@ use tokio::sync::RwLock;
 // 目标切换回调函数类型
 pub type TargetSwitchCallback = Arc<dyn Fn(&str, &str, &str) + Send + Sync>;
 
+// 健康检查调度粒度
+const SCHEDULER_TICK: Duration = Duration::from_secs(1);
+// 目标不属于任何规则时的检查超时
+const DEFAULT_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
 #[derive(Debug, Clone)]
 pub struct TargetInfo {
     pub original: String,
@@ -18,29 +29,154 @@ pub struct TargetInfo {
     pub healthy: bool,
     pub last_check: Instant,
     pub fail_count: u32,
+    pub success_count: u32,              // 连续成功次数（rise判定使用）
+    pub healthy_since: Instant,          // 最近一次变为健康的时间（回切延迟使用）
+    pub weight: u32,                     // 规则内的目标权重（加权轮询使用）
+    pub check_latency: Option<Duration>, // 最近一次健康检查耗时，None表示尚未检查
+    pub srv_weight: Option<u32>,         // SRV/TXT记录中的权重，优先于规则配置的weights
+}
+
+impl TargetInfo {
+    // 新解析出的地址先视为健康，首次健康检查结果直接生效
+    fn new(original: &str, resolved: SocketAddr) -> Self {
+        Self {
+            original: original.to_string(),
+            resolved,
+            healthy: true,
+            last_check: Instant::now(),
+            fail_count: 0,
+            success_count: 0,
+            healthy_since: Instant::now(),
+            weight: 1,
+            check_latency: None,
+            srv_weight: None,
+        }
+    }
+
+    fn from_resolved(original: &str, resolved: &ResolvedAddr) -> Self {
+        Self {
+            srv_weight: resolved.weight,
+            ..Self::new(original, resolved.addr)
+        }
+    }
 }
 
+// 目标缓存：原始目标字符串 -> 解析出的每个地址的状态（域名目标可能有多个A/AAAA记录）
+type TargetCache = Arc<DashMap<String, Vec<TargetInfo>>>;
+
 #[derive(Debug)]
 pub struct RuleInfo {
     pub targets: Vec<TargetInfo>,
     pub selected_target: Option<TargetInfo>,
     pub last_update: Instant,
+    pub strategy: LoadBalanceStrategy,
+    pub rr_counter: AtomicUsize, // 轮询计数器
+    pub switch_count: u64,       // 目标切换次数
+}
+
+// 规则状态快照
+#[derive(Debug, Clone)]
+pub struct RuleSnapshot {
+    pub name: String,
+    pub selected_target: Option<TargetInfo>,
+    pub targets: Vec<TargetInfo>,
+    pub switch_count: u64,
+}
+
+// 健康检查结果摘要
+pub struct HealthCheckSummary {
+    pub healthy: usize,
+    pub unhealthy: usize,
+    pub changes: Vec<String>, // 本轮状态变化，如 "1.2.3.4:80 异常"
+}
+
+impl fmt::Display for HealthCheckSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} 个地址健康，{} 个地址异常",
+            self.healthy, self.unhealthy
+        )?;
+        if !self.changes.is_empty() {
+            write!(f, " [{}]", self.changes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// 目标检查计划
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CheckSchedule {
+    interval: Duration,
+    timeout: Duration,
+    fall: u32,
+    rise: u32,
+}
+
+// 按规则的动态更新配置计算每个目标的检查间隔、超时和故障判定次数
+// 多个规则共享同一目标时取最严格的值：间隔、超时和fall取最小，rise取最大
+fn check_schedules(config: &Config) -> HashMap<String, CheckSchedule> {
+    let global = config.get_dynamic_update_config();
+    let mut schedules: HashMap<String, CheckSchedule> = HashMap::new();
+
+    for rule in &config.rules {
+        let dynamic = rule.get_dynamic_update_config(&global);
+        let schedule = CheckSchedule {
+            interval: Duration::from_secs(dynamic.get_check_interval().max(1)),
+            timeout: Duration::from_secs(dynamic.get_connection_timeout().max(1)),
+            fall: dynamic.get_fall().max(1),
+            rise: dynamic.get_rise().max(1),
+        };
+        for target in &rule.targets {
+            schedules
+                .entry(target.clone())
+                .and_modify(|existing| {
+                    existing.interval = existing.interval.min(schedule.interval);
+                    existing.timeout = existing.timeout.min(schedule.timeout);
+                    existing.fall = existing.fall.min(schedule.fall);
+                    existing.rise = existing.rise.max(schedule.rise);
+                })
+                .or_insert(schedule);
+        }
+    }
+
+    schedules
+}
+
+// 活跃连接计数守卫 - 连接结束时自动减少目标的活跃连接数（最少连接策略使用）
+pub struct ConnectionGuard {
+    active_connections: Arc<DashMap<SocketAddr, usize>>,
+    target: SocketAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if let Some(mut count) = self.active_connections.get_mut(&self.target) {
+            *count = count.saturating_sub(1);
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct CommonManager {
-    config: Config,
-    target_cache: Arc<DashMap<String, TargetInfo>>,
+    config: Arc<RwLock<Config>>,
+    target_cache: TargetCache,
     rule_infos: Arc<RwLock<DashMap<String, RuleInfo>>>,
+    active_connections: Arc<DashMap<SocketAddr, usize>>,
+    passive_failures: Arc<DashMap<(String, SocketAddr), Vec<Instant>>>, // 被动检测：按规则和地址记录窗口内的连接失败时间
+    dns: Arc<DnsResolvers>, // 共享DNS解析器（带TTL缓存），按规则的dns_profile选择
     target_switch_callback: Option<TargetSwitchCallback>,
 }
 
 impl CommonManager {
     pub fn new(config: Config) -> Self {
         Self {
-            config,
+            dns: Arc::new(DnsResolvers::new(&config)),
+            config: Arc::new(RwLock::new(config)),
             target_cache: Arc::new(DashMap::new()),
             rule_infos: Arc::new(RwLock::new(DashMap::new())),
+            active_connections: Arc::new(DashMap::new()),
+            passive_failures: Arc::new(DashMap::new()),
             target_switch_callback: None,
         }
     }
@@ -50,22 +186,24 @@ impl CommonManager {
     }
 
     pub async fn initialize(&self) -> Result<()> {
+        let config = self.config.read().await.clone();
+
         // 1. DNS解析阶段：解析所有目标地址
-        for rule in &self.config.rules {
+        for rule in &config.rules {
             if let Err(e) = self.initialize_rule_targets(rule).await {
                 error!("规则 {} DNS解析失败: {}", rule.name, e);
             }
         }
 
         // 2. 初始健康检查阶段：批量并发检查所有目标
-        let health_check_result = Self::batch_health_check(&self.target_cache, &self.config).await;
+        let health_check_result = Self::batch_health_check(&self.target_cache, &config, None).await;
         info!("初始健康检查完成: {health_check_result}");
 
         // 3. 选择最优地址阶段：为每个规则选择最佳目标
         Self::update_rule_targets(
             &self.rule_infos,
             &self.target_cache,
-            &self.config,
+            &config,
             &self.target_switch_callback,
         )
         .await;
@@ -97,23 +235,111 @@ impl CommonManager {
         Ok(())
     }
 
+    // 热重载：应用新配置，只处理变化的规则，未变化规则的目标和健康状态保持不变
+    pub async fn apply_config(&self, new_config: Config, diff: &RuleDiff) -> Result<()> {
+        let old_config = std::mem::replace(&mut *self.config.write().await, new_config.clone());
+        self.dns.reconfigure(&new_config);
+
+        // 1. 移除已删除的规则
+        {
+            let rule_infos = self.rule_infos.write().await;
+            for rule in &diff.removed {
+                rule_infos.remove(&rule.name);
+                info!("规则 {} 已移除", rule.name);
+            }
+        }
+
+        // 2. 清理不再被任何规则引用的目标
+        self.target_cache.retain(|target_str, _| {
+            new_config
+                .rules
+                .iter()
+                .any(|rule| rule.targets.contains(target_str))
+        });
+
+        // 3. 解析设置变化的目标不复用旧结果：规则换了dns_profile，或域名的hosts固定地址变化
+        let invalidated = stale_targets(&old_config, &new_config);
+        for target_str in &invalidated {
+            if self.target_cache.remove(target_str).is_some() {
+                info!("目标 {target_str} 的解析设置变化，按新配置重新解析");
+            }
+        }
+
+        // 4. 解析新增和变化规则的目标（已存在的目标复用当前解析结果）
+        let mut rules_to_initialize: Vec<&crate::config::ForwardRule> = diff
+            .added
+            .iter()
+            .chain(diff.restarted.iter())
+            .chain(diff.updated.iter())
+            .collect();
+        for rule in &new_config.rules {
+            let affected = rule.targets.iter().any(|t| invalidated.contains(t));
+            if affected && !rules_to_initialize.iter().any(|r| r.name == rule.name) {
+                rules_to_initialize.push(rule);
+            }
+        }
+        for rule in rules_to_initialize {
+            if let Err(e) = self.initialize_rule_targets(rule).await {
+                error!("规则 {} DNS解析失败: {}", rule.name, e);
+            }
+        }
+
+        // 5. DNS/hosts配置变化：立即按新配置重新解析所有域名目标（保留仍存在地址的健康状态）
+        if diff.resolver_changed {
+            Self::update_dns_resolutions(
+                &self.target_cache,
+                &self.rule_infos,
+                &new_config,
+                &self.dns,
+                &self.target_switch_callback,
+                None,
+            )
+            .await;
+        }
+
+        // 6. 健康检查并重新选择目标
+        let health_check_result =
+            Self::batch_health_check(&self.target_cache, &new_config, None).await;
+        info!("重载后健康检查完成: {health_check_result}");
+
+        Self::update_rule_targets(
+            &self.rule_infos,
+            &self.target_cache,
+            &new_config,
+            &self.target_switch_callback,
+        )
+        .await;
+
+        Ok(())
+    }
+
     async fn initialize_rule_targets(&self, rule: &crate::config::ForwardRule) -> Result<()> {
         let mut targets = Vec::new();
 
         for target_str in rule.targets.iter() {
-            let dns_config = self.config.get_dns_config();
-            match resolve_target(target_str, &dns_config).await {
-                Ok(resolved_addr) => {
-                    let target_info = TargetInfo {
-                        original: target_str.clone(),
-                        resolved: resolved_addr,
-                        healthy: true,
-                        last_check: Instant::now(),
-                        fail_count: 0,
-                    };
-
-                    targets.push(target_info.clone());
-                    self.target_cache.insert(target_str.clone(), target_info);
+            // 已解析过的目标（多规则共享或热重载）保留当前解析结果和健康状态
+            if let Some(existing) = self.target_cache.get(target_str) {
+                targets.extend(existing.iter().cloned());
+                continue;
+            }
+
+            match resolve_target(target_str, &self.dns.for_rule(rule)).await {
+                Ok(resolved_addrs) => {
+                    let infos: Vec<TargetInfo> = resolved_addrs
+                        .iter()
+                        .map(|resolved| TargetInfo::from_resolved(target_str, resolved))
+                        .collect();
+                    if infos.len() > 1 {
+                        info!(
+                            "目标 {} 解析到{}个地址: {}",
+                            target_str,
+                            infos.len(),
+                            join_addrs(&resolved_addrs)
+                        );
+                    }
+
+                    targets.extend(infos.iter().cloned());
+                    self.target_cache.insert(target_str.clone(), infos);
                 }
                 Err(e) => {
                     error!("无法解析目标 {target_str}: {e}");
@@ -121,65 +347,128 @@ impl CommonManager {
             }
         }
 
+        let rule_infos = self.rule_infos.write().await;
+
+        // 热重载时保留当前选中的目标和切换计数，避免无谓切换
+        let (selected_target, switch_count) = rule_infos
+            .get(&rule.name)
+            .map(|info| (info.selected_target.clone(), info.switch_count))
+            .unwrap_or((None, 0));
+
         let rule_info = RuleInfo {
             targets,
-            selected_target: None,
+            selected_target,
             last_update: Instant::now(),
+            strategy: rule.get_strategy(),
+            rr_counter: AtomicUsize::new(0),
+            switch_count,
         };
 
-        self.rule_infos
-            .write()
-            .await
-            .insert(rule.name.clone(), rule_info);
+        rule_infos.insert(rule.name.clone(), rule_info);
         Ok(())
     }
 
     async fn start_health_check_task(&self) {
         let target_cache = self.target_cache.clone();
         let rule_infos = self.rule_infos.clone();
-        let config = self.config.clone(); // 传递配置信息
+        let shared_config = self.config.clone(); // 共享配置，热重载后自动生效
         let callback = self.target_switch_callback.clone();
+        let dns = self.dns.clone();
 
         tokio::spawn(async move {
-            let check_interval = config.get_dynamic_update_config().get_check_interval();
-            let mut interval = tokio::time::interval(Duration::from_secs(check_interval));
-            let mut _check_count = 0;
-
-            info!("启动定期健康检查任务，间隔{}秒", check_interval);
-
+            let check_interval = shared_config
+                .read()
+                .await
+                .get_dynamic_update_config()
+                .get_check_interval();
+            info!("启动定期健康检查任务，默认间隔{}秒", check_interval);
+
+            // 每个目标的下次检查时间，按所属规则的检查间隔调度
+            let mut next_check: HashMap<String, Instant> = HashMap::new();
             let mut last_status = None;
 
             loop {
-                // 等待检查间隔
-                interval.tick().await;
-
-                // 1. 重新解析所有域名的DNS（每次检查间隔都解析）
-                Self::update_dns_resolutions(&target_cache, &rule_infos, &config, &callback).await;
-
-                // 2. 对所有目标进行健康检查（根据协议类型选择检查方式）
-                let current_status = Self::batch_health_check(&target_cache, &config).await;
+                tokio::time::sleep(SCHEDULER_TICK).await;
+
+                // 每轮按最新配置计算检查计划（只在读锁内计算，不复制配置），热重载后检查间隔和超时立即生效
+                let schedules = check_schedules(&*shared_config.read().await);
+                let now = Instant::now();
+
+                // 找出到期的目标（启动/热重载时已检查过，新目标一个间隔后首次检查）
+                next_check.retain(|target, _| schedules.contains_key(target));
+                let mut due_targets = Vec::new();
+                for (target, schedule) in &schedules {
+                    let next = next_check
+                        .entry(target.clone())
+                        .or_insert(now + schedule.interval);
+                    // 间隔缩短时提前下次检查
+                    if *next > now + schedule.interval {
+                        *next = now + schedule.interval;
+                    }
+                    if *next <= now {
+                        due_targets.push(target.clone());
+                        *next = now + schedule.interval;
+                    }
+                }
+                if due_targets.is_empty() {
+                    continue;
+                }
+                // 有到期目标时才取配置快照，检查期间不持有读锁，避免阻塞热重载
+                let config = shared_config.read().await.clone();
+
+                // 1. 重新解析到期目标的DNS（每次检查间隔都解析）
+                Self::update_dns_resolutions(
+                    &target_cache,
+                    &rule_infos,
+                    &config,
+                    &dns,
+                    &callback,
+                    Some(&due_targets),
+                )
+                .await;
+
+                // 2. 对到期目标进行健康检查（根据规则配置选择检查方式）
+                let summary =
+                    Self::batch_health_check(&target_cache, &config, Some(&due_targets)).await;
 
                 // 3. 异常后立即切换到可用的最高优先级地址
                 Self::update_rule_targets(&rule_infos, &target_cache, &config, &callback).await;
 
-                // 只在状态变化时记录日志，减少重复输出
+                // 按所有目标统计状态，只在状态变化时记录日志，减少重复输出
+                let current_status = HealthCheckSummary {
+                    healthy: target_cache
+                        .iter()
+                        .flat_map(|entry| entry.value().clone())
+                        .filter(|t| t.healthy)
+                        .count(),
+                    unhealthy: target_cache
+                        .iter()
+                        .flat_map(|entry| entry.value().clone())
+                        .filter(|t| !t.healthy)
+                        .count(),
+                    changes: summary.changes,
+                }
+                .to_string();
                 if last_status != Some(current_status.clone()) {
                     info!("健康检查状态: {current_status}");
-                    last_status = Some(current_status.clone());
+                    last_status = Some(current_status);
                 }
             }
         });
     }
 
-    // DNS解析更新 - 每次检查间隔都重新解析所有域名，确保地址变化能及时反映
+    // DNS解析更新 - 每次检查间隔都重新解析到期目标的域名，未过期的解析结果由DNS缓存直接返回
     async fn update_dns_resolutions(
-        target_cache: &Arc<DashMap<String, TargetInfo>>,
+        target_cache: &TargetCache,
         rule_infos: &Arc<RwLock<DashMap<String, RuleInfo>>>,
         config: &Config,
+        dns: &Arc<DnsResolvers>,
         callback: &Option<TargetSwitchCallback>,
+        only_targets: Option<&[String]>,
     ) {
         let targets: Vec<_> = target_cache
             .iter()
+            .filter(|entry| only_targets.is_none_or(|only| only.contains(entry.key())))
             .map(|entry| (entry.key().clone(), entry.value().clone()))
             .collect();
 
@@ -187,42 +476,58 @@ impl CommonManager {
         let mut any_updated = false;
 
         // 并发处理每个域名的DNS解析，各自独立，不再有批量触发逻辑
-        for (target_str, target_info) in targets {
+        for (target_str, target_infos) in targets {
             // 只处理域名，跳过IP:PORT格式
             if target_str.parse::<std::net::SocketAddr>().is_err() && target_str.contains('.') {
                 let target_cache_clone = target_cache.clone();
-                let dns_config = config.get_dns_config(); // 在spawn外获取配置
+                let resolver = dns.for_target(config, &target_str);
                 let task = tokio::spawn(async move {
-                    match resolve_target(&target_str, &dns_config).await {
+                    match resolve_target(&target_str, &resolver).await {
                         Ok(new_resolved) => {
-                            let mut updated_info = target_info.clone();
-                            let has_changed = new_resolved != target_info.resolved;
+                            let old_resolved: Vec<ResolvedAddr> = target_infos
+                                .iter()
+                                .map(|t| ResolvedAddr {
+                                    addr: t.resolved,
+                                    weight: t.srv_weight,
+                                })
+                                .collect();
+                            let has_changed = new_resolved != old_resolved;
 
                             if has_changed {
                                 info!(
                                     "目标 {} DNS解析变化: {} -> {}",
-                                    target_str, target_info.resolved, new_resolved
+                                    target_str,
+                                    join_addrs(&old_resolved),
+                                    join_addrs(&new_resolved)
                                 );
                             }
 
-                            updated_info.resolved = new_resolved;
-                            updated_info.last_check = Instant::now();
+                            // 仍存在的地址保留健康状态，新地址等待后续健康检查
+                            let updated_infos: Vec<TargetInfo> = new_resolved
+                                .iter()
+                                .map(|resolved| {
+                                    let mut info = target_infos
+                                        .iter()
+                                        .find(|t| t.resolved == resolved.addr)
+                                        .cloned()
+                                        .unwrap_or_else(|| {
+                                            TargetInfo::from_resolved(&target_str, resolved)
+                                        });
+                                    info.srv_weight = resolved.weight;
+                                    info.last_check = Instant::now();
+                                    info
+                                })
+                                .collect();
                             // 注意：健康状态将由后续的batch_health_check更新
 
                             // 验证完成后更新缓存
-                            target_cache_clone.insert(target_str.clone(), updated_info);
+                            target_cache_clone.insert(target_str.clone(), updated_infos);
 
                             Some(has_changed) // 只有地址变化时才标记为有更新
                         }
                         Err(e) => {
-                            // DNS解析失败，单独标记此域名，不触发批量操作
-                            let mut failed_info = target_info.clone();
-                            failed_info.last_check = Instant::now();
-                            failed_info.healthy = false;
-                            failed_info.fail_count += 1;
-
-                            warn!("目标 {target_str} DNS解析失败: {e}");
-                            target_cache_clone.insert(target_str, failed_info);
+                            // DNS解析失败时保留上次解析结果和健康状态，地址是否可用由健康检查按fall判定
+                            warn!("目标 {target_str} DNS解析失败，继续使用上次解析结果: {e}");
                             Some(false)
                         }
                     }
@@ -249,52 +554,79 @@ impl CommonManager {
 
     // 健康检查 - 统一的健康检查函数，支持UDP和非UDP规则
     async fn batch_health_check(
-        target_cache: &Arc<DashMap<String, TargetInfo>>,
+        target_cache: &TargetCache,
         config: &Config,
-    ) -> String {
+        only_targets: Option<&[String]>,
+    ) -> HealthCheckSummary {
+        // 域名目标的每个解析地址单独检查
         let targets: Vec<_> = target_cache
             .iter()
-            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .filter(|entry| only_targets.is_none_or(|only| only.contains(entry.key())))
+            .flat_map(|entry| {
+                entry
+                    .value()
+                    .iter()
+                    .map(|info| (entry.key().clone(), info.clone()))
+                    .collect::<Vec<_>>()
+            })
             .collect();
 
-        // 建立目标地址到规则的映射，用于决定健康检查协议
-        let mut target_to_protocol = std::collections::HashMap::new();
+        // 建立目标地址到规则的映射，用于决定健康检查方式
+        // 多个规则共享同一目标时，优先使用明确配置了health_check的规则
+        let mut target_to_check: HashMap<&String, Option<HealthCheckConfig>> = HashMap::new();
         for rule in &config.rules {
             let protocols = rule.get_protocols();
             for target_str in &rule.targets {
                 // 简化协议分类：UDP 和 非UDP
-                let check_protocol = if protocols.len() == 1 && protocols[0] == "udp" {
-                    "udp" // 纯UDP规则
-                } else {
-                    "tcp" // 非UDP规则（TCP、TCP+UDP）
+                let check = match &rule.health_check {
+                    Some(check) => Some(check.clone()),
+                    None if protocols.len() == 1 && protocols[0] == "udp" => None, // 纯UDP规则
+                    None => Some(HealthCheckConfig::default()), // 非UDP规则：TCP连接检查
                 };
-                target_to_protocol.insert(target_str.clone(), check_protocol);
+                let entry = target_to_check.entry(target_str).or_insert(None);
+                if rule.health_check.is_some() || entry.is_none() {
+                    *entry = check;
+                }
             }
         }
 
+        // 各目标的超时时间（共享目标取最严格的规则配置）
+        let schedules = check_schedules(config);
+
         // 并发执行健康检查
         let mut tasks = Vec::new();
         for (target_str, target_info) in targets {
-            let protocol_to_check = target_to_protocol
+            let check = target_to_check
                 .get(&target_str)
-                .copied()
-                .unwrap_or("tcp");
+                .cloned()
+                .unwrap_or_else(|| Some(HealthCheckConfig::default()));
+            let schedule = schedules.get(&target_str).copied();
+            let timeout = schedule
+                .map(|schedule| schedule.timeout)
+                .unwrap_or(DEFAULT_CHECK_TIMEOUT);
+            let (fall, rise) = schedule.map_or((1, 1), |schedule| (schedule.fall, schedule.rise));
 
-            let dns_config = config.get_dns_config(); // 在spawn外获取配置
             let task = tokio::spawn(async move {
                 let start = Instant::now();
 
-                // 根据协议类型决定健康检查方式
-                let result = if protocol_to_check == "udp" {
+                // 根据规则配置决定健康检查方式
+                let result = match check {
                     // UDP规则：跳过健康检查，认为DNS解析成功的目标都是健康的
-                    Ok(Duration::from_millis(0))
-                } else {
-                    // 非UDP规则：进行TCP连接测试
-                    crate::utils::test_connection(&target_str, &dns_config).await
+                    None => Ok(Duration::from_millis(0)),
+                    // 非UDP规则：对已解析地址执行TCP/HTTP/TLS检查
+                    Some(check) => {
+                        crate::health::check_target(
+                            target_info.resolved,
+                            &target_str,
+                            &check,
+                            timeout,
+                        )
+                        .await
+                    }
                 };
 
                 let check_time = start.elapsed();
-                (target_str, target_info, result, check_time)
+                (target_str, target_info, result, check_time, fall, rise)
             });
             tasks.push(task);
         }
@@ -305,63 +637,83 @@ impl CommonManager {
         let mut status_changes = Vec::new();
 
         for task in tasks {
-            if let Ok((target_str, mut target_info, result, _check_time)) = task.await {
+            if let Ok((target_str, mut target_info, result, check_time, fall, rise)) = task.await {
                 let old_healthy = target_info.healthy;
+                // 首次检查直接以结果为准，之后按fall/rise连续次数判定
+                let first_check = target_info.check_latency.is_none();
+                let label = target_label(&target_str, target_info.resolved);
+                target_info.check_latency = Some(check_time);
+                target_info.last_check = Instant::now();
 
                 match result {
                     Ok(_) => {
-                        target_info.healthy = true;
                         target_info.fail_count = 0; // 成功时重置失败计数
-                        target_info.last_check = Instant::now();
-                        success_count += 1;
-
-                        // 如果之前不健康，现在恢复了
-                        if !old_healthy {
-                            status_changes.push(format!("{target_str} 恢复"));
+                        target_info.success_count += 1;
+
+                        if !old_healthy && (first_check || target_info.success_count >= rise) {
+                            target_info.healthy = true;
+                            target_info.healthy_since = Instant::now();
+                            info!(
+                                "目标 {} 恢复健康: 连续成功{}次",
+                                label, target_info.success_count
+                            );
+                            status_changes.push(format!("{label} 恢复"));
+                        } else if !old_healthy {
+                            debug!(
+                                "目标 {} 检查成功 {}/{}，暂不恢复",
+                                label, target_info.success_count, rise
+                            );
                         }
                     }
-                    Err(_e) => {
+                    Err(e) => {
+                        target_info.success_count = 0;
                         target_info.fail_count += 1;
-                        target_info.last_check = Instant::now();
 
-                        // 失败1次就标记为不健康，快速切换
-                        if target_info.fail_count >= 1 && old_healthy {
+                        if old_healthy && (first_check || target_info.fail_count >= fall) {
                             target_info.healthy = false;
-                            status_changes.push(format!("{target_str} 异常"));
-                        }
-
-                        // 统计时仍然按当前健康状态计算
-                        if target_info.healthy {
-                            success_count += 1;
-                        } else {
-                            fail_count += 1;
+                            warn!(
+                                "目标 {} 标记为异常: 连续失败{}次，最近错误: {}",
+                                label, target_info.fail_count, e
+                            );
+                            status_changes.push(format!("{label} 异常"));
+                        } else if old_healthy {
+                            info!(
+                                "目标 {} 检查失败 {}/{}，暂不标记异常: {}",
+                                label, target_info.fail_count, fall, e
+                            );
                         }
                     }
                 }
 
-                target_cache.insert(target_str, target_info);
+                // 统计时按当前健康状态计算
+                if target_info.healthy {
+                    success_count += 1;
+                } else {
+                    fail_count += 1;
+                }
+
+                // 只更新仍存在的地址（检查期间DNS可能已变化）
+                if let Some(mut infos) = target_cache.get_mut(&target_str) {
+                    if let Some(info) = infos
+                        .iter_mut()
+                        .find(|info| info.resolved == target_info.resolved)
+                    {
+                        *info = target_info;
+                    }
+                }
             }
         }
 
-        // 生成状态摘要
-        let healthy_addresses = success_count;
-        let unhealthy_addresses = fail_count;
-
-        if !status_changes.is_empty() {
-            format!(
-                "{} 个地址健康，{} 个地址异常 [{}]",
-                healthy_addresses,
-                unhealthy_addresses,
-                status_changes.join(", ")
-            )
-        } else {
-            format!("{healthy_addresses} 个地址健康，{unhealthy_addresses} 个地址异常")
+        HealthCheckSummary {
+            healthy: success_count,
+            unhealthy: fail_count,
+            changes: status_changes,
         }
     }
 
     async fn update_rule_targets(
         rule_infos: &Arc<RwLock<DashMap<String, RuleInfo>>>,
-        target_cache: &Arc<DashMap<String, TargetInfo>>,
+        target_cache: &TargetCache,
         config: &Config,
         callback: &Option<TargetSwitchCallback>,
     ) {
@@ -371,19 +723,29 @@ impl CommonManager {
             let rule_name = entry.key().clone();
             let rule_info = entry.value_mut();
 
-            // 获取当前规则的目标列表（直接从配置中查找）
-            let rule_targets =
-                if let Some(rule) = config.rules.iter().find(|r| r.name == *rule_name) {
-                    &rule.targets
-                } else {
-                    continue;
-                };
+            // 获取当前规则配置（直接从配置中查找）
+            let rule = if let Some(rule) = config.rules.iter().find(|r| r.name == *rule_name) {
+                rule
+            } else {
+                continue;
+            };
+            rule_info.strategy = rule.get_strategy();
+            let failback_delay = Duration::from_secs(
+                rule.get_dynamic_update_config(&config.get_dynamic_update_config())
+                    .get_failback_delay(),
+            );
 
-            // 更新目标信息
+            // 更新目标信息（权重按规则配置设置）
             let mut updated_targets = Vec::new();
-            for target_str in rule_targets {
-                if let Some(target_info) = target_cache.get(target_str) {
-                    updated_targets.push(target_info.clone());
+            for (index, target_str) in rule.targets.iter().enumerate() {
+                if let Some(target_infos) = target_cache.get(target_str) {
+                    for target_info in target_infos.iter() {
+                        let mut target_info = target_info.clone();
+                        target_info.weight = target_info
+                            .srv_weight
+                            .unwrap_or_else(|| rule.get_target_weight(index));
+                        updated_targets.push(target_info);
+                    }
                 }
             }
 
@@ -391,6 +753,7 @@ impl CommonManager {
             let new_selected_target = select_best_target_with_stickiness(
                 &updated_targets,
                 rule_info.selected_target.as_ref(),
+                failback_delay,
             );
 
             // 检查是否有健康目标，避免重复的无健康目标警告
@@ -421,6 +784,7 @@ impl CommonManager {
                             "规则 {} 切换: {} -> {}",
                             rule_name, old.resolved, new.resolved
                         );
+                        rule_info.switch_count += 1;
 
                         // 立即调用回调通知防火墙更新
                         if let Some(cb) = callback {
@@ -494,6 +858,293 @@ impl CommonManager {
         anyhow::bail!("没有可用的目标: {}", rule_name)
     }
 
+    // DNS解析统计（指标使用）
+    pub fn dns_stats(&self) -> DnsStats {
+        self.dns.stats()
+    }
+
+    // 当前配置快照
+    pub async fn config(&self) -> Config {
+        self.config.read().await.clone()
+    }
+
+    // 所有规则的目标状态快照（管理接口使用）
+    pub async fn rule_snapshots(&self) -> Vec<RuleSnapshot> {
+        let rule_infos = self.rule_infos.read().await;
+        let mut snapshots: Vec<RuleSnapshot> = rule_infos
+            .iter()
+            .map(|entry| RuleSnapshot {
+                name: entry.key().clone(),
+                selected_target: entry.value().selected_target.clone(),
+                targets: entry.value().targets.clone(),
+                switch_count: entry.value().switch_count,
+            })
+            .collect();
+        snapshots.sort_by(|a, b| a.name.cmp(&b.name));
+        snapshots
+    }
+
+    // 所有目标的缓存状态快照（管理接口使用）
+    pub fn target_snapshots(&self) -> Vec<TargetInfo> {
+        let mut targets: Vec<TargetInfo> = self
+            .target_cache
+            .iter()
+            .flat_map(|entry| entry.value().clone())
+            .collect();
+        targets.sort_by(|a, b| a.original.cmp(&b.original));
+        targets
+    }
+
+    // 立即对单个规则的目标执行健康检查并重新选择目标
+    pub async fn check_rule_now(&self, rule_name: &str) -> Result<String> {
+        let config = self.config().await;
+        let rule_targets = Self::rule_targets(&config, rule_name)?;
+
+        let result =
+            Self::batch_health_check(&self.target_cache, &config, Some(&rule_targets)).await;
+        Self::update_rule_targets(
+            &self.rule_infos,
+            &self.target_cache,
+            &config,
+            &self.target_switch_callback,
+        )
+        .await;
+
+        info!("规则 {rule_name} 手动健康检查: {result}");
+        Ok(result.to_string())
+    }
+
+    // 立即重新解析单个规则的域名目标并重新选择目标
+    pub async fn resolve_rule_now(&self, rule_name: &str) -> Result<()> {
+        let config = self.config().await;
+        let rule_targets = Self::rule_targets(&config, rule_name)?;
+
+        for target in &rule_targets {
+            if let Some(hostname) = target_hostname(target) {
+                self.dns.for_target(&config, target).invalidate(hostname);
+            }
+        }
+        Self::update_dns_resolutions(
+            &self.target_cache,
+            &self.rule_infos,
+            &config,
+            &self.dns,
+            &self.target_switch_callback,
+            Some(&rule_targets),
+        )
+        .await;
+        Self::update_rule_targets(
+            &self.rule_infos,
+            &self.target_cache,
+            &config,
+            &self.target_switch_callback,
+        )
+        .await;
+
+        info!("规则 {rule_name} 手动DNS重新解析完成");
+        Ok(())
+    }
+
+    fn rule_targets(config: &Config, rule_name: &str) -> Result<Vec<String>> {
+        config
+            .rules
+            .iter()
+            .find(|rule| rule.name == rule_name)
+            .map(|rule| rule.targets.clone())
+            .ok_or_else(|| anyhow::anyhow!("规则不存在: {}", rule_name))
+    }
+
+    // 按规则的负载均衡策略为单个连接/会话选择目标
+    pub async fn select_target(
+        &self,
+        rule_name: &str,
+        client_addr: Option<SocketAddr>,
+    ) -> Result<SocketAddr> {
+        let rule_infos = self.rule_infos.read().await;
+
+        if let Some(rule_info) = rule_infos.get(rule_name) {
+            if rule_info.strategy != LoadBalanceStrategy::Priority {
+                let healthy: Vec<&TargetInfo> =
+                    rule_info.targets.iter().filter(|t| t.healthy).collect();
+                if let Some(addr) = pick_target(
+                    rule_info.strategy,
+                    &healthy,
+                    &rule_info.rr_counter,
+                    client_addr,
+                    &self.active_connections,
+                ) {
+                    return Ok(addr);
+                }
+            }
+
+            // 优先级策略或无健康目标时，使用当前选中的目标
+            if let Some(target) = &rule_info.selected_target {
+                return Ok(target.resolved);
+            }
+        }
+
+        anyhow::bail!("没有可用的目标: {}", rule_name)
+    }
+
+    // UDP会话选择目标：非优先级策略下，会话目标仍健康时保持不变
+    pub async fn select_session_target(
+        &self,
+        rule_name: &str,
+        client_addr: SocketAddr,
+        current: Option<SocketAddr>,
+    ) -> Result<SocketAddr> {
+        if let Some(current) = current {
+            let rule_infos = self.rule_infos.read().await;
+            let keep_current = rule_infos.get(rule_name).is_some_and(|rule_info| {
+                rule_info.strategy != LoadBalanceStrategy::Priority
+                    && rule_info
+                        .targets
+                        .iter()
+                        .any(|t| t.healthy && t.resolved == current)
+            });
+            if keep_current {
+                return Ok(current);
+            }
+        }
+
+        self.select_target(rule_name, Some(client_addr)).await
+    }
+
+    // TCP连接重试策略：重试次数和含重试在内的连接总超时
+    pub async fn connect_policy(&self, rule_name: &str) -> (u32, Duration) {
+        let config = self.config.read().await;
+        match config.rules.iter().find(|rule| rule.name == rule_name) {
+            Some(rule) => (
+                rule.get_connect_retries(),
+                Duration::from_secs(rule.get_connect_deadline()),
+            ),
+            None => (0, Duration::from_secs(10)),
+        }
+    }
+
+    // 连接失败后的下一个重试目标：按规则目标顺序取第一个未尝试过的健康目标
+    pub async fn next_connect_target(
+        &self,
+        rule_name: &str,
+        tried: &[SocketAddr],
+    ) -> Option<SocketAddr> {
+        let rule_infos = self.rule_infos.read().await;
+        let rule_info = rule_infos.get(rule_name)?;
+        rule_info
+            .targets
+            .iter()
+            .find(|t| t.healthy && !tried.contains(&t.resolved))
+            .map(|t| t.resolved)
+    }
+
+    // 记录目标的活跃连接，返回的守卫在连接结束时自动释放
+    pub fn track_connection(&self, target: SocketAddr) -> ConnectionGuard {
+        *self.active_connections.entry(target).or_insert(0) += 1;
+        ConnectionGuard {
+            active_connections: self.active_connections.clone(),
+            target,
+        }
+    }
+
+    // 被动健康检测：转发器上报真实连接失败，窗口内失败次数达到阈值时立即标记异常并切换
+    pub async fn report_connect_failure(&self, rule_name: &str, target: SocketAddr, reason: &str) {
+        // 只在读锁内取出该规则的被动检测阈值和目标，不复制整个配置
+        // 目标的健康状态由共享同一目标的规则共用，关闭了被动检测的规则共享的目标不做标记
+        let (threshold, window, targets) = {
+            let config = self.config.read().await;
+            let Some(rule) = config.rules.iter().find(|r| r.name == rule_name) else {
+                return;
+            };
+            let global = config.get_dynamic_update_config();
+            let dynamic = rule.get_dynamic_update_config(&global);
+            let targets: Vec<String> = rule
+                .targets
+                .iter()
+                .filter(|target| {
+                    !config.rules.iter().any(|other| {
+                        other.targets.contains(target)
+                            && other
+                                .get_dynamic_update_config(&global)
+                                .get_passive_failures()
+                                == 0
+                    })
+                })
+                .cloned()
+                .collect();
+            (
+                dynamic.get_passive_failures(),
+                Duration::from_secs(dynamic.get_passive_window()),
+                targets,
+            )
+        };
+        if threshold == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let failures = {
+            let mut failures = self
+                .passive_failures
+                .entry((rule_name.to_string(), target))
+                .or_default();
+            failures.retain(|time| now.duration_since(*time) < window);
+            failures.push(now);
+            let count = failures.len() as u32;
+            if count >= threshold {
+                failures.clear();
+            }
+            count
+        };
+        debug!(
+            "规则 {} 目标 {} 连接失败 {}/{}: {}",
+            rule_name, target, failures, threshold, reason
+        );
+        if failures < threshold {
+            return;
+        }
+
+        // 只标记该规则自己的目标中解析到此地址的条目
+        let mut marked = Vec::new();
+        for original in &targets {
+            let Some(mut infos) = self.target_cache.get_mut(original) else {
+                continue;
+            };
+            for info in infos.iter_mut() {
+                if info.resolved == target && info.healthy {
+                    info.healthy = false;
+                    info.success_count = 0;
+                    info.fail_count += 1;
+                    marked.push(target_label(original, target));
+                }
+            }
+        }
+        if marked.is_empty() {
+            return;
+        }
+
+        warn!(
+            "目标 {} 被动检测标记为异常: {}秒内{}次连接失败，最近错误: {}",
+            marked.join(", "),
+            window.as_secs(),
+            failures,
+            reason
+        );
+        let config = self.config.read().await;
+        Self::update_rule_targets(
+            &self.rule_infos,
+            &self.target_cache,
+            &config,
+            &self.target_switch_callback,
+        )
+        .await;
+    }
+
+    // 连接成功时清除该目标的失败记录，只统计连续失败
+    pub fn report_connect_success(&self, rule_name: &str, target: SocketAddr) {
+        self.passive_failures
+            .remove(&(rule_name.to_string(), target));
+    }
+
     #[allow(dead_code)]
     pub async fn get_best_target_string(&self, rule_name: &str) -> Result<String> {
         let addr = self.get_best_target(rule_name).await?;
@@ -501,10 +1152,119 @@ impl CommonManager {
     }
 }
 
+// 热重载时解析设置变化的目标：所属规则的dns_profile变化，或目标域名的hosts固定地址变化
+fn stale_targets(old_config: &Config, new_config: &Config) -> HashSet<String> {
+    let old_hosts = old_config.get_hosts();
+    let new_hosts = new_config.get_hosts();
+    let mut stale = HashSet::new();
+
+    for rule in &new_config.rules {
+        let profile_changed = old_config
+            .rules
+            .iter()
+            .any(|old| old.name == rule.name && old.dns_profile != rule.dns_profile);
+        for target in &rule.targets {
+            let hostname = target
+                .strip_prefix("srv://")
+                .unwrap_or(target)
+                .split(':')
+                .next()
+                .unwrap_or_default()
+                .trim_end_matches('.')
+                .to_ascii_lowercase();
+            if profile_changed || old_hosts.get(&hostname) != new_hosts.get(&hostname) {
+                stale.insert(target.clone());
+            }
+        }
+    }
+
+    stale
+}
+
+// 日志中的目标名称：域名目标附带解析出的地址
+fn target_label(original: &str, resolved: SocketAddr) -> String {
+    if original == resolved.to_string() {
+        original.to_string()
+    } else {
+        format!("{original}({resolved})")
+    }
+}
+
+fn join_addrs(addrs: &[ResolvedAddr]) -> String {
+    addrs
+        .iter()
+        .map(|resolved| match resolved.weight {
+            Some(weight) => format!("{}(权重{})", resolved.addr, weight),
+            None => resolved.addr.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// 负载均衡目标选择 - 在健康目标中按策略选择（优先级策略由调用方处理）
+fn pick_target(
+    strategy: LoadBalanceStrategy,
+    healthy: &[&TargetInfo],
+    rr_counter: &AtomicUsize,
+    client_addr: Option<SocketAddr>,
+    active_connections: &DashMap<SocketAddr, usize>,
+) -> Option<SocketAddr> {
+    if healthy.is_empty() {
+        return None;
+    }
+
+    let target = match strategy {
+        LoadBalanceStrategy::Priority => healthy[0],
+        LoadBalanceStrategy::RoundRobin => {
+            let index = rr_counter.fetch_add(1, Ordering::Relaxed) % healthy.len();
+            healthy[index]
+        }
+        LoadBalanceStrategy::WeightedRoundRobin => {
+            let total_weight: usize = healthy.iter().map(|t| t.weight.max(1) as usize).sum();
+            let mut position = rr_counter.fetch_add(1, Ordering::Relaxed) % total_weight;
+            let mut chosen = healthy[0];
+            for target in healthy {
+                let weight = target.weight.max(1) as usize;
+                if position < weight {
+                    chosen = target;
+                    break;
+                }
+                position -= weight;
+            }
+            chosen
+        }
+        LoadBalanceStrategy::LeastConnections => {
+            // 连接数相同时按配置顺序（优先级）选择
+            healthy
+                .iter()
+                .min_by_key(|t| {
+                    active_connections
+                        .get(&t.resolved)
+                        .map(|count| *count)
+                        .unwrap_or(0)
+                })
+                .copied()
+                .unwrap_or(healthy[0])
+        }
+        LoadBalanceStrategy::SourceIpHash => match client_addr {
+            Some(client) => {
+                let mut hasher = DefaultHasher::new();
+                client.ip().hash(&mut hasher);
+                healthy[(hasher.finish() % healthy.len() as u64) as usize]
+            }
+            None => healthy[0],
+        },
+    };
+
+    Some(target.resolved)
+}
+
 // 智能目标选择算法 - 优先级优先策略，确保切换到最高优先级健康地址
+// 高优先级目标恢复后需稳定 failback_delay 才切回，避免链路抖动时反复切换
 fn select_best_target_with_stickiness(
     targets: &[TargetInfo],
     current_target: Option<&TargetInfo>,
+    failback_delay: Duration,
 ) -> Option<TargetInfo> {
     if targets.is_empty() {
         return None;
@@ -542,7 +1302,31 @@ fn select_best_target_with_stickiness(
             if target.healthy {
                 // 找到优先级最高的健康目标
                 if let Some(current) = current_target {
-                    if target.resolved != current.resolved {
+                    // 当前目标仍健康时，等待高优先级目标稳定后再回切
+                    let current_healthy = healthy_targets
+                        .iter()
+                        .find(|t| t.resolved == current.resolved);
+                    let stable_for = target.healthy_since.elapsed();
+                    if let Some(current_healthy) = current_healthy {
+                        if target.resolved != current.resolved && stable_for < failback_delay {
+                            log::debug!(
+                                "高优先级地址 {} 已恢复{}秒，回切延迟{}秒，暂时保持 {}",
+                                target.resolved,
+                                stable_for.as_secs(),
+                                failback_delay.as_secs(),
+                                current.resolved
+                            );
+                            return Some((*current_healthy).clone());
+                        }
+                    }
+                    if target.resolved != current.resolved && current_healthy.is_some() {
+                        log::info!(
+                            "回切到高优先级地址: {} 替换 {} (已稳定{}秒)",
+                            target.resolved,
+                            current.resolved,
+                            stable_for.as_secs()
+                        );
+                    } else if target.resolved != current.resolved {
                         log::info!(
                             "切换到最高优先级健康地址: {} 替换 {}",
                             target.resolved,
@@ -570,3 +1354,399 @@ fn select_best_target_with_stickiness(
 
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(addr: &str, weight: u32) -> TargetInfo {
+        TargetInfo {
+            original: addr.to_string(),
+            resolved: addr.parse().unwrap(),
+            healthy: true,
+            last_check: Instant::now(),
+            fail_count: 0,
+            success_count: 0,
+            healthy_since: Instant::now(),
+            weight,
+            check_latency: None,
+            srv_weight: None,
+        }
+    }
+
+    #[test]
+    fn test_check_schedules() {
+        let yaml = r#"
+logging: {level: info, format: text}
+network: {listen_addrs: [127.0.0.1]}
+dynamic_update: {check_interval: 10, connection_timeout: 3}
+rules:
+  - name: slow
+    listen_port: 80
+    targets: ["10.0.0.1:80", "10.0.0.2:80"]
+  - name: fast
+    listen_port: 443
+    targets: ["10.0.0.2:80"]
+    dynamic_update: {check_interval: 2, connection_timeout: 5}
+"#;
+        let config: Config = serde_yml::from_str(yaml).unwrap();
+        let schedules = check_schedules(&config);
+        let secs = Duration::from_secs;
+
+        assert_eq!(schedules["10.0.0.1:80"].interval, secs(10));
+        assert_eq!(schedules["10.0.0.1:80"].timeout, secs(3));
+        // 共享目标取最严格的间隔和超时
+        assert_eq!(schedules["10.0.0.2:80"].interval, secs(2));
+        assert_eq!(schedules["10.0.0.2:80"].timeout, secs(3));
+    }
+
+    #[test]
+    fn test_failback_delay() {
+        let mut primary = target("10.0.0.1:80", 1);
+        let mut backup = target("10.0.0.2:80", 1);
+        let delay = Duration::from_secs(30);
+        let select = |primary: &TargetInfo, backup: &TargetInfo, delay| {
+            let targets = vec![primary.clone(), backup.clone()];
+            select_best_target_with_stickiness(&targets, Some(backup), delay)
+                .unwrap()
+                .resolved
+        };
+
+        // 高优先级目标刚恢复：保持当前目标，无延迟时立即切回
+        assert_eq!(select(&primary, &backup, delay), backup.resolved);
+        assert_eq!(select(&primary, &backup, Duration::ZERO), primary.resolved);
+
+        // 稳定时间超过回切延迟后切回
+        primary.healthy_since = Instant::now() - Duration::from_secs(60);
+        assert_eq!(select(&primary, &backup, delay), primary.resolved);
+
+        // 当前目标异常时不等待
+        primary.healthy_since = Instant::now();
+        backup.healthy = false;
+        assert_eq!(select(&primary, &backup, delay), primary.resolved);
+    }
+
+    #[tokio::test]
+    async fn test_rise_fall() {
+        // 先占用再释放端口，得到一个未监听的地址
+        let addr = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let yaml = format!(
+            "logging: {{level: info, format: text}}\n\
+             network: {{listen_addrs: [127.0.0.1]}}\n\
+             dynamic_update: {{fall: 2, rise: 2}}\n\
+             rules: [{{name: r, listen_port: 80, protocol: tcp, targets: [\"{addr}\"]}}]"
+        );
+        let config: Config = serde_yml::from_str(&yaml).unwrap();
+        let cache = Arc::new(DashMap::new());
+        let mut info = target(&addr.to_string(), 1);
+        info.check_latency = Some(Duration::ZERO); // 跳过首次检查
+        cache.insert(addr.to_string(), vec![info]);
+
+        let healthy = |cache: &DashMap<String, Vec<TargetInfo>>| {
+            cache.get(&addr.to_string()).unwrap()[0].healthy
+        };
+
+        CommonManager::batch_health_check(&cache, &config, None).await;
+        assert!(healthy(&cache), "失败1次不应标记异常");
+        CommonManager::batch_health_check(&cache, &config, None).await;
+        assert!(!healthy(&cache));
+
+        let _listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+        CommonManager::batch_health_check(&cache, &config, None).await;
+        assert!(!healthy(&cache), "成功1次不应恢复");
+        CommonManager::batch_health_check(&cache, &config, None).await;
+        assert!(healthy(&cache));
+    }
+
+    #[tokio::test]
+    async fn test_multi_address_target() {
+        // 同一域名解析出两个地址：一个在监听，一个未监听，各自独立检查
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+        let dead = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [127.0.0.1]}\n\
+                    rules: [{name: r, listen_port: 80, protocol: tcp, targets: [\"svc.test:80\"]}]";
+        let manager = CommonManager::new(serde_yml::from_str(yaml).unwrap());
+        manager.target_cache.insert(
+            "svc.test:80".to_string(),
+            vec![
+                TargetInfo::new("svc.test:80", dead),
+                TargetInfo::new("svc.test:80", live),
+            ],
+        );
+        manager
+            .initialize_rule_targets(&manager.config().await.rules[0])
+            .await
+            .unwrap();
+
+        let config = manager.config().await;
+        CommonManager::batch_health_check(&manager.target_cache, &config, None).await;
+        CommonManager::update_rule_targets(
+            &manager.rule_infos,
+            &manager.target_cache,
+            &config,
+            &None,
+        )
+        .await;
+
+        let health: Vec<bool> = manager
+            .target_cache
+            .get("svc.test:80")
+            .unwrap()
+            .iter()
+            .map(|t| t.healthy)
+            .collect();
+        assert_eq!(health, vec![false, true]);
+        assert_eq!(manager.get_best_target("r").await.unwrap(), live);
+        assert_eq!(manager.rule_snapshots().await[0].targets.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dns_failure_keeps_last_resolution() {
+        // DNS上游不可用时，已解析的地址保持原有健康状态，不因一次解析失败全部标记异常
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [127.0.0.1]}\n\
+                    dns: {mode: custom, servers: [\"tcp://127.0.0.1:1\"], timeout: 1, attempts: 1}\n\
+                    rules: [{name: r, listen_port: 80, protocol: tcp, targets: [\"svc.test:80\"]}]";
+        let manager = CommonManager::new(serde_yml::from_str(yaml).unwrap());
+        let addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        manager.target_cache.insert(
+            "svc.test:80".to_string(),
+            vec![TargetInfo::new("svc.test:80", addr)],
+        );
+
+        let config = manager.config().await;
+        CommonManager::update_dns_resolutions(
+            &manager.target_cache,
+            &manager.rule_infos,
+            &config,
+            &manager.dns,
+            &None,
+            None,
+        )
+        .await;
+
+        let infos = manager.target_cache.get("svc.test:80").unwrap().clone();
+        assert_eq!(infos.len(), 1);
+        assert_eq!(infos[0].resolved, addr);
+        assert!(infos[0].healthy);
+        assert_eq!(infos[0].fail_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_reload_dns_profile_change() {
+        // 规则改用另一个dns_profile后，目标不再复用旧解析器得到的地址
+        let yaml = |profile: &str| {
+            format!(
+                "logging: {{level: info, format: text}}\n\
+                 network: {{listen_addrs: [127.0.0.1]}}\n\
+                 dns_profiles: {{lan: {{mode: custom, servers: [\"tcp://127.0.0.1:1\"], timeout: 1, attempts: 1}}}}\n\
+                 rules: [{{name: r, listen_port: 80, protocol: tcp, targets: [\"svc.test:80\"]{profile}}}]"
+            )
+        };
+        let old: Config = serde_yml::from_str(&yaml("")).unwrap();
+        let new: Config = serde_yml::from_str(&yaml(", dns_profile: lan")).unwrap();
+
+        let manager = CommonManager::new(old.clone());
+        let stale: SocketAddr = "127.0.0.2:80".parse().unwrap();
+        manager.target_cache.insert(
+            "svc.test:80".to_string(),
+            vec![TargetInfo::new("svc.test:80", stale)],
+        );
+
+        let diff = old.diff_rules(&new);
+        assert_eq!(diff.updated.len(), 1);
+        manager.apply_config(new, &diff).await.unwrap();
+        // 新的DNS配置无法解析该域名，旧地址也不能继续使用
+        assert!(!manager.target_cache.contains_key("svc.test:80"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_hosts_only() {
+        // 只修改hosts固定地址时，重载后目标立即按新地址解析
+        let first = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let yaml = |addr: SocketAddr| {
+            format!(
+                "logging: {{level: info, format: text}}\n\
+                 network: {{listen_addrs: [127.0.0.1]}}\n\
+                 hosts: {{app.test: \"{addr}\"}}\n\
+                 rules: [{{name: r, listen_port: 80, protocol: tcp, targets: [\"app.test\"]}}]"
+            )
+        };
+        let old: Config = serde_yml::from_str(&yaml(first.local_addr().unwrap())).unwrap();
+        let new: Config = serde_yml::from_str(&yaml(second.local_addr().unwrap())).unwrap();
+
+        let manager = CommonManager::new(old.clone());
+        manager.initialize().await.unwrap();
+        assert_eq!(
+            manager.get_best_target("r").await.unwrap(),
+            first.local_addr().unwrap()
+        );
+
+        let diff = old.diff_rules(&new);
+        assert!(diff.resolver_changed);
+        manager.apply_config(new, &diff).await.unwrap();
+        assert_eq!(
+            manager.get_best_target("r").await.unwrap(),
+            second.local_addr().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_passive_failures() {
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [127.0.0.1]}\n\
+                    dynamic_update: {passive_failures: 2, passive_window: 10}\n\
+                    rules: [{name: r, listen_port: 80, targets: [\"10.0.0.1:80\"]}]";
+        let manager = CommonManager::new(serde_yml::from_str(yaml).unwrap());
+        let info = target("10.0.0.1:80", 1);
+        let addr = info.resolved;
+        manager
+            .target_cache
+            .insert(info.original.clone(), vec![info]);
+        let healthy = || manager.target_cache.get("10.0.0.1:80").unwrap()[0].healthy;
+
+        // 中间的成功连接会清除失败记录
+        manager
+            .report_connect_failure("r", addr, "连接目标超时")
+            .await;
+        manager.report_connect_success("r", addr);
+        manager
+            .report_connect_failure("r", addr, "连接目标超时")
+            .await;
+        assert!(healthy());
+
+        manager
+            .report_connect_failure("r", addr, "连接目标超时")
+            .await;
+        assert!(!healthy());
+    }
+
+    #[tokio::test]
+    async fn test_passive_failures_per_rule() {
+        // a开启被动检测，b关闭；a还有一个与b共享的目标，以及一个解析到同一地址的独立目标
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [127.0.0.1]}\n\
+                    hosts: {svc.test: 10.0.0.1}\n\
+                    rules:\n\
+                    - {name: a, listen_port: 80, targets: [\"svc.test:80\", \"10.0.0.1:80\"], dynamic_update: {passive_failures: 1}}\n\
+                    - {name: b, listen_port: 81, targets: [\"10.0.0.1:80\"], dynamic_update: {passive_failures: 0}}";
+        let manager = CommonManager::new(serde_yml::from_str(yaml).unwrap());
+        let addr: SocketAddr = "10.0.0.1:80".parse().unwrap();
+        for original in ["svc.test:80", "10.0.0.1:80"] {
+            manager
+                .target_cache
+                .insert(original.to_string(), vec![TargetInfo::new(original, addr)]);
+        }
+        let healthy = |original: &str| manager.target_cache.get(original).unwrap()[0].healthy;
+
+        // 关闭被动检测的规则不计数也不标记
+        manager
+            .report_connect_failure("b", addr, "连接目标超时")
+            .await;
+        assert!(healthy("svc.test:80") && healthy("10.0.0.1:80"));
+        assert!(manager.passive_failures.is_empty());
+
+        // a只标记自己独有的目标，与b共享的目标保持健康
+        manager
+            .report_connect_failure("a", addr, "连接目标超时")
+            .await;
+        assert!(!healthy("svc.test:80"));
+        assert!(healthy("10.0.0.1:80"));
+    }
+
+    #[test]
+    fn test_pick_target_strategies() {
+        let a = target("10.0.0.1:80", 3);
+        let b = target("10.0.0.2:80", 1);
+        let healthy = vec![&a, &b];
+        let active = DashMap::new();
+
+        // 轮询：依次选择
+        let counter = AtomicUsize::new(0);
+        let picks: Vec<_> = (0..4)
+            .map(|_| {
+                pick_target(
+                    LoadBalanceStrategy::RoundRobin,
+                    &healthy,
+                    &counter,
+                    None,
+                    &active,
+                )
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(picks, vec![a.resolved, b.resolved, a.resolved, b.resolved]);
+
+        // 加权轮询：3:1
+        let counter = AtomicUsize::new(0);
+        let a_count = (0..8)
+            .filter(|_| {
+                pick_target(
+                    LoadBalanceStrategy::WeightedRoundRobin,
+                    &healthy,
+                    &counter,
+                    None,
+                    &active,
+                ) == Some(a.resolved)
+            })
+            .count();
+        assert_eq!(a_count, 6);
+
+        // 最少连接：选择活跃连接少的目标
+        active.insert(a.resolved, 2);
+        active.insert(b.resolved, 1);
+        assert_eq!(
+            pick_target(
+                LoadBalanceStrategy::LeastConnections,
+                &healthy,
+                &counter,
+                None,
+                &active,
+            ),
+            Some(b.resolved)
+        );
+
+        // 源IP哈希：同一客户端始终选择同一目标
+        let client: SocketAddr = "192.168.1.50:50000".parse().unwrap();
+        let first = pick_target(
+            LoadBalanceStrategy::SourceIpHash,
+            &healthy,
+            &counter,
+            Some(client),
+            &active,
+        );
+        let other_port: SocketAddr = "192.168.1.50:50001".parse().unwrap();
+        assert_eq!(
+            first,
+            pick_target(
+                LoadBalanceStrategy::SourceIpHash,
+                &healthy,
+                &counter,
+                Some(other_port),
+                &active,
+            )
+        );
+
+        assert_eq!(
+            pick_target(
+                LoadBalanceStrategy::RoundRobin,
+                &[],
+                &counter,
+                None,
+                &active
+            ),
+            None
+        );
+    }
+}
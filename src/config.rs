@@ -1,5 +1,6 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
@@ -8,9 +9,13 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub network: NetworkConfig,
     pub buffer_size: Option<usize>,
+    pub drain_timeout: Option<u64>, // 关闭时等待现有TCP连接结束的最长秒数，默认10秒
     pub rules: Vec<ForwardRule>,
     pub dynamic_update: Option<DynamicUpdateConfig>,
     pub dns: Option<DnsConfig>,
+    pub dns_profiles: Option<HashMap<String, DnsConfig>>, // 命名DNS配置，规则通过dns_profile引用
+    pub hosts: Option<HashMap<String, HostsEntry>>,       // 固定解析，优先于任何DNS查询
+    pub admin: Option<AdminConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,25 +24,49 @@ pub struct LoggingConfig {
     pub format: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub listen_addrs: Vec<String>,
 }
 
 impl NetworkConfig {
     pub fn contains_wildcard(&self) -> bool {
-        self.listen_addrs.iter().any(|addr| addr == "0.0.0.0")
+        self.ips().iter().any(|ip| is_wildcard(ip))
     }
 
-    pub fn first(&self) -> String {
+    // 监听地址列表（去掉IPv6的方括号）
+    pub fn ips(&self) -> Vec<String> {
         self.listen_addrs
-            .first()
-            .unwrap_or(&"0.0.0.0".to_string())
-            .clone()
+            .iter()
+            .map(|addr| {
+                addr.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_string()
+            })
+            .collect()
+    }
+
+    // 每个监听地址拼接端口，IPv6地址加方括号，如 [::1]:443
+    pub fn socket_addrs(&self, port: u16) -> Vec<String> {
+        self.ips()
+            .iter()
+            .map(|ip| {
+                if ip.contains(':') {
+                    format!("[{ip}]:{port}")
+                } else {
+                    format!("{ip}:{port}")
+                }
+            })
+            .collect()
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// 监听所有接口的地址，DNAT规则不限制目标地址
+pub fn is_wildcard(ip: &str) -> bool {
+    ip == "0.0.0.0" || ip == "::"
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ForwardRule {
     pub name: String,
     pub listen_port: u16,
@@ -45,21 +74,378 @@ pub struct ForwardRule {
     pub protocols: Option<Vec<String>>, // 新增：支持多协议
     pub buffer_size: Option<usize>,
     pub targets: Vec<String>,
+    pub strategy: Option<String>,  // 负载均衡策略，默认priority
+    pub weights: Option<Vec<u32>>, // 目标权重，与targets一一对应（加权轮询使用）
     pub dynamic_update: Option<DynamicUpdateConfig>,
+    pub health_check: Option<HealthCheckConfig>, // 健康检查方式，默认TCP连接检查
+    pub connect_retries: Option<u32>, // TCP连接目标失败时依次重试其他健康目标的次数，默认0
+    pub connect_deadline: Option<u64>, // 含重试在内的连接总超时秒数，默认10秒
+    pub dns_profile: Option<String>,  // 使用dns_profiles中的命名DNS配置，默认使用全局dns
+    pub masquerade: Option<bool>, // 内核态是否对转发流量做SNAT，默认true；目标经网关回程时可关闭
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// 健康检查配置
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HealthCheckConfig {
+    #[serde(rename = "type")]
+    pub check_type: Option<String>, // tcp | http | https | tls | udp | dns | stun，默认tcp
+    pub method: Option<String>,          // HTTP请求方法，默认GET
+    pub path: Option<String>,            // HTTP请求路径，默认 /
+    pub expected_status: Option<String>, // 期望状态码，如 "200" 或 "200-399"，默认200-399
+    pub body_contains: Option<String>,   // 响应内容需要包含的字符串
+    pub host: Option<String>,            // Host头和TLS SNI，默认使用目标地址
+    pub tls_verify: Option<bool>,        // 是否校验证书，默认校验，自签名证书可设为false
+    pub payload: Option<String>,         // UDP探测发送内容（文本）
+    pub payload_hex: Option<String>,     // UDP探测发送内容（十六进制）
+    pub expect: Option<String>,          // UDP期望回复前缀（文本）
+    pub expect_hex: Option<String>,      // UDP期望回复前缀（十六进制）
+    pub expect_regex: Option<String>,    // UDP期望回复匹配的正则表达式
+    pub dns_query: Option<String>,       // dns探测查询的域名，默认根域名 "."
+    pub timeout: Option<u64>,            // 检查超时秒数，默认3秒
+}
+
+// 健康检查类型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HealthCheckType {
+    Tcp,   // TCP连接成功即健康
+    Http,  // HTTP请求返回期望状态码
+    Https, // HTTPS请求返回期望状态码
+    Tls,   // TLS握手成功即健康
+    Udp,   // 发送UDP探测包，收到期望回复即健康
+    Dns,   // 预设：发送DNS查询，收到DNS响应即健康
+    Stun,  // 预设：发送STUN绑定请求，收到绑定响应即健康
+}
+
+impl HealthCheckType {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "tcp" => Some(Self::Tcp),
+            "http" => Some(Self::Http),
+            "https" => Some(Self::Https),
+            "tls" => Some(Self::Tls),
+            "udp" => Some(Self::Udp),
+            "dns" => Some(Self::Dns),
+            "stun" => Some(Self::Stun),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Http => "http",
+            Self::Https => "https",
+            Self::Tls => "tls",
+            Self::Udp => "udp",
+            Self::Dns => "dns",
+            Self::Stun => "stun",
+        }
+    }
+}
+
+impl HealthCheckConfig {
+    pub fn get_type(&self) -> HealthCheckType {
+        self.check_type
+            .as_deref()
+            .and_then(HealthCheckType::parse)
+            .unwrap_or(HealthCheckType::Tcp)
+    }
+
+    pub fn get_tls_verify(&self) -> bool {
+        self.tls_verify.unwrap_or(true)
+    }
+
+    pub fn get_method(&self) -> &str {
+        self.method.as_deref().unwrap_or("GET")
+    }
+
+    pub fn get_path(&self) -> &str {
+        self.path.as_deref().unwrap_or("/")
+    }
+
+    // UDP探测发送内容，text和hex二选一
+    pub fn get_payload(&self) -> Result<Option<Vec<u8>>> {
+        match (&self.payload, &self.payload_hex) {
+            (Some(_), Some(_)) => anyhow::bail!("payload和payload_hex不能同时配置"),
+            (Some(text), None) => Ok(Some(text.as_bytes().to_vec())),
+            (None, Some(hex)) => Ok(Some(crate::utils::decode_hex(hex)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    // UDP期望回复前缀，text和hex二选一
+    pub fn get_expect_prefix(&self) -> Result<Option<Vec<u8>>> {
+        match (&self.expect, &self.expect_hex) {
+            (Some(_), Some(_)) => anyhow::bail!("expect和expect_hex不能同时配置"),
+            (Some(text), None) => Ok(Some(text.as_bytes().to_vec())),
+            (None, Some(hex)) => Ok(Some(crate::utils::decode_hex(hex)?)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    pub fn get_expect_regex(&self) -> Result<Option<regex::bytes::Regex>> {
+        self.expect_regex
+            .as_deref()
+            .map(regex::bytes::Regex::new)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("无效的expect_regex: {}", e))
+    }
+
+    fn validate_udp_probe(&self) -> Result<()> {
+        let payload = self.get_payload()?;
+        self.get_expect_prefix()?;
+        self.get_expect_regex()?;
+        if self.get_type() == HealthCheckType::Udp && payload.is_none() {
+            anyhow::bail!("udp健康检查需要配置payload或payload_hex");
+        }
+        if self.timeout == Some(0) {
+            anyhow::bail!("健康检查超时时间必须大于0");
+        }
+        Ok(())
+    }
+
+    // 期望状态码范围（闭区间）
+    pub fn get_status_range(&self) -> Result<(u16, u16)> {
+        let Some(expected) = &self.expected_status else {
+            return Ok((200, 399));
+        };
+        let parse = |code: &str| -> Result<u16> {
+            code.trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|code| (100..=599).contains(code))
+                .ok_or_else(|| anyhow::anyhow!("无效的HTTP状态码: {}", code))
+        };
+        match expected.split_once('-') {
+            Some((min, max)) => {
+                let (min, max) = (parse(min)?, parse(max)?);
+                if min > max {
+                    anyhow::bail!("无效的HTTP状态码范围: {}", expected);
+                }
+                Ok((min, max))
+            }
+            None => {
+                let code = parse(expected)?;
+                Ok((code, code))
+            }
+        }
+    }
+}
+
+// 负载均衡策略
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadBalanceStrategy {
+    Priority,           // 优先级故障转移：全部流量走第一个健康目标
+    RoundRobin,         // 轮询
+    WeightedRoundRobin, // 加权轮询
+    LeastConnections,   // 最少连接
+    SourceIpHash,       // 源IP哈希：同一客户端固定到同一目标
+}
+
+impl LoadBalanceStrategy {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "priority" => Some(Self::Priority),
+            "round_robin" => Some(Self::RoundRobin),
+            "weighted_round_robin" => Some(Self::WeightedRoundRobin),
+            "least_connections" => Some(Self::LeastConnections),
+            "source_ip_hash" => Some(Self::SourceIpHash),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Priority => "priority",
+            Self::RoundRobin => "round_robin",
+            Self::WeightedRoundRobin => "weighted_round_robin",
+            Self::LeastConnections => "least_connections",
+            Self::SourceIpHash => "source_ip_hash",
+        }
+    }
+}
+
+// 域名目标的IP版本偏好：决定使用哪些A/AAAA记录以及它们的优先顺序
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpPreference {
+    Ipv4Only, // 只使用A记录
+    Ipv6Only, // 只使用AAAA记录
+    PreferV4, // A和AAAA都使用，IPv4优先
+    PreferV6, // A和AAAA都使用，IPv6优先
+}
+
+impl IpPreference {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ipv4-only" => Some(Self::Ipv4Only),
+            "ipv6-only" => Some(Self::Ipv6Only),
+            "prefer-v4" => Some(Self::PreferV4),
+            "prefer-v6" => Some(Self::PreferV6),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ipv4Only => "ipv4-only",
+            Self::Ipv6Only => "ipv6-only",
+            Self::PreferV4 => "prefer-v4",
+            Self::PreferV6 => "prefer-v6",
+        }
+    }
+}
+
+// DNS服务器来源
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DnsMode {
+    System,       // 系统配置（/etc/resolv.conf）的服务器和搜索域
+    Custom,       // 只使用servers
+    SystemCustom, // 系统服务器在前，servers作为补充
+}
+
+impl DnsMode {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "system" => Some(Self::System),
+            "custom" => Some(Self::Custom),
+            "system+custom" => Some(Self::SystemCustom),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::Custom => "custom",
+            Self::SystemCustom => "system+custom",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DynamicUpdateConfig {
     pub check_interval: Option<u64>,
     pub connection_timeout: Option<u64>,
     // 移除 health_check_interval，使用统一的 check_interval
+    pub fall: Option<u32>,             // 连续失败多少次标记为异常，默认1
+    pub rise: Option<u32>,             // 连续成功多少次标记为恢复，默认1
+    pub failback_delay: Option<u64>,   // 高优先级目标恢复后稳定多少秒再切回，默认0
+    pub passive_failures: Option<u32>, // 窗口内真实连接失败多少次立即标记异常，默认0（关闭）
+    pub passive_window: Option<u64>,   // 被动检测统计窗口秒数，默认10
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// 管理接口配置（可选）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AdminConfig {
+    pub listen: String, // 监听地址，建议只监听本地，如 127.0.0.1:9090
+}
+
+// hosts条目：单个地址或地址列表，地址为IP（域名:端口目标使用）或IP:PORT（纯域名目标使用）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HostsEntry {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl HostsEntry {
+    pub fn addrs(&self) -> Vec<String> {
+        match self {
+            Self::One(addr) => vec![addr.clone()],
+            Self::Many(addrs) => addrs.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DnsConfig {
+    pub mode: Option<String>, // system | custom | system+custom，默认配置了servers时为custom，否则为system
+    #[serde(default)]
     pub servers: Vec<String>,
-    pub timeout: Option<u64>,    // DNS查询超时秒数，默认2秒
-    pub attempts: Option<usize>, // DNS查询重试次数，默认2次
+    pub timeout: Option<u64>,          // DNS查询超时秒数，默认2秒
+    pub attempts: Option<usize>,       // DNS查询重试次数，默认2次
+    pub min_ttl: Option<u64>,          // 解析结果最短缓存秒数，默认0（按记录TTL）
+    pub max_ttl: Option<u64>,          // 解析结果最长缓存秒数，默认300
+    pub ip_preference: Option<String>, // ipv4-only | ipv6-only | prefer-v4 | prefer-v6，默认prefer-v4
+    pub txt_key: Option<String>,       // TXT记录HMAC签名密钥，配置后只接受签名正确的v=sf1记录
+}
+
+impl DnsConfig {
+    pub fn get_mode(&self) -> DnsMode {
+        match self.mode.as_deref().and_then(DnsMode::parse) {
+            Some(mode) => mode,
+            None if self.servers.is_empty() => DnsMode::System,
+            None => DnsMode::Custom,
+        }
+    }
+
+    pub fn get_min_ttl(&self) -> u64 {
+        self.min_ttl.unwrap_or(0)
+    }
+
+    pub fn get_max_ttl(&self) -> u64 {
+        self.max_ttl.unwrap_or(300)
+    }
+
+    pub fn get_ip_preference(&self) -> IpPreference {
+        self.ip_preference
+            .as_deref()
+            .and_then(IpPreference::parse)
+            .unwrap_or(IpPreference::PreferV4)
+    }
+
+    pub fn validate(&self, label: &str) -> Result<()> {
+        if let Some(mode) = &self.mode {
+            if DnsMode::parse(mode).is_none() {
+                anyhow::bail!("{}: 不支持的mode {}", label, mode);
+            }
+        }
+        if self.get_mode() != DnsMode::System && self.servers.is_empty() {
+            anyhow::bail!(
+                "{}: {}模式至少需要一个DNS服务器",
+                label,
+                self.get_mode().as_str()
+            );
+        }
+        for server in &self.servers {
+            crate::dns::DnsUpstream::parse(server)?;
+        }
+        if let Some(preference) = &self.ip_preference {
+            if IpPreference::parse(preference).is_none() {
+                anyhow::bail!("{}: 不支持的ip_preference {}", label, preference);
+            }
+        }
+        if self.get_min_ttl() > self.get_max_ttl() {
+            anyhow::bail!(
+                "{}: min_ttl({})不能大于max_ttl({})",
+                label,
+                self.get_min_ttl(),
+                self.get_max_ttl()
+            );
+        }
+        Ok(())
+    }
+}
+
+// 配置重载时的规则差异（按规则名称对比）
+#[derive(Debug, Default)]
+pub struct RuleDiff {
+    pub added: Vec<ForwardRule>,     // 新增规则：需要启动
+    pub removed: Vec<ForwardRule>,   // 删除规则：需要停止
+    pub restarted: Vec<ForwardRule>, // 监听相关配置变化：需要重启
+    pub updated: Vec<ForwardRule>,   // 仅目标/动态更新配置变化：原地更新
+    pub settings_changed: bool,      // 全局设置（动态更新/DNS/hosts/drain_timeout）变化
+    pub resolver_changed: bool,      // DNS/dns_profiles/hosts变化：需要重新解析所有域名目标
+}
+
+impl RuleDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.restarted.is_empty()
+            && self.updated.is_empty()
+            && !self.settings_changed
+    }
 }
 
 impl Config {
@@ -82,6 +468,11 @@ impl Config {
             config.dynamic_update = Some(DynamicUpdateConfig {
                 check_interval: Some(5),     // 5秒健康检查间隔，快速故障检测
                 connection_timeout: Some(3), // 3秒连接超时，快速故障检测
+                fall: None,
+                rise: None,
+                failback_delay: None,
+                passive_failures: None,
+                passive_window: None,
             });
         }
 
@@ -103,11 +494,72 @@ impl Config {
             anyhow::bail!("至少需要配置一个转发规则");
         }
 
+        let mut listen_ips: Vec<std::net::IpAddr> = Vec::new();
+        for ip in self.network.ips() {
+            match ip.parse::<std::net::IpAddr>() {
+                Ok(addr) if listen_ips.contains(&addr) => anyhow::bail!("监听地址重复: {}", ip),
+                Ok(addr) => listen_ips.push(addr),
+                Err(_) => anyhow::bail!("监听地址无效: {}（应为IPv4或IPv6地址）", ip),
+            }
+        }
+        // 通配地址与其覆盖范围内的其他地址同时监听同一端口会失败（EADDRINUSE）
+        // :: 为双栈监听，同时覆盖所有IPv4地址
+        if listen_ips.len() > 1
+            && listen_ips
+                .iter()
+                .any(|ip| ip.is_ipv6() && ip.is_unspecified())
+        {
+            anyhow::bail!("监听地址 :: 已同时监听所有IPv4和IPv6地址，不能与其他监听地址同时配置");
+        }
+        let ipv4_count = listen_ips.iter().filter(|ip| ip.is_ipv4()).count();
+        if ipv4_count > 1
+            && listen_ips
+                .iter()
+                .any(|ip| ip.is_ipv4() && ip.is_unspecified())
+        {
+            anyhow::bail!("监听地址 0.0.0.0 已监听所有IPv4地址，不能与其他IPv4地址同时配置");
+        }
+
+        if let Some(admin) = &self.admin {
+            if admin.listen.parse::<std::net::SocketAddr>().is_err() {
+                anyhow::bail!("管理接口监听地址无效: {}", admin.listen);
+            }
+        }
+
+        if let Some(dynamic_update) = &self.dynamic_update {
+            dynamic_update.validate("全局")?;
+        }
+
+        self.get_dns_config().validate("DNS配置")?;
+        let dns_profiles = self.dns_profiles.clone().unwrap_or_default();
+        for (name, dns_config) in &dns_profiles {
+            dns_config.validate(&format!("DNS配置 {}", name))?;
+        }
+
+        for (hostname, entry) in self.hosts.iter().flatten() {
+            let addrs = entry.addrs();
+            if addrs.is_empty() {
+                anyhow::bail!("hosts {}: 至少需要一个地址", hostname);
+            }
+            for addr in addrs {
+                if addr.parse::<std::net::IpAddr>().is_err()
+                    && addr.parse::<std::net::SocketAddr>().is_err()
+                {
+                    anyhow::bail!("hosts {}: 无效的地址 {}（应为IP或IP:PORT）", hostname, addr);
+                }
+            }
+        }
+
         for (i, rule) in self.rules.iter().enumerate() {
             if rule.name.is_empty() {
                 anyhow::bail!("规则 {}: 名称不能为空", i + 1);
             }
 
+            // 热重载和转发器都按名称区分规则，名称必须唯一
+            if self.rules[..i].iter().any(|other| other.name == rule.name) {
+                anyhow::bail!("规则 {}: 名称重复", rule.name);
+            }
+
             if rule.listen_port == 0 {
                 anyhow::bail!("规则 {}: 端口号不能为0", rule.name);
             }
@@ -131,28 +583,155 @@ impl Config {
                     }
                 }
             }
+
+            // 验证负载均衡策略
+            if let Some(strategy) = &rule.strategy {
+                if LoadBalanceStrategy::parse(strategy).is_none() {
+                    anyhow::bail!("规则 {}: 不支持的负载均衡策略 {}", rule.name, strategy);
+                }
+            }
+
+            // 验证目标权重
+            if let Some(weights) = &rule.weights {
+                if weights.len() != rule.targets.len() {
+                    anyhow::bail!(
+                        "规则 {}: weights数量({})必须与targets数量({})一致",
+                        rule.name,
+                        weights.len(),
+                        rule.targets.len()
+                    );
+                }
+                if weights.contains(&0) {
+                    anyhow::bail!("规则 {}: 权重必须大于0", rule.name);
+                }
+            }
+
+            if rule.connect_deadline == Some(0) {
+                anyhow::bail!("规则 {}: connect_deadline必须大于0", rule.name);
+            }
+
+            if let Some(dynamic_update) = &rule.dynamic_update {
+                dynamic_update.validate(&format!("规则 {}", rule.name))?;
+            }
+
+            if let Some(profile) = &rule.dns_profile {
+                if !dns_profiles.contains_key(profile) {
+                    anyhow::bail!(
+                        "规则 {}: dns_profile {} 未在dns_profiles中定义",
+                        rule.name,
+                        profile
+                    );
+                }
+            }
+            // 同一目标的解析结果在规则间共享，必须使用相同的DNS配置
+            for other in &self.rules[..i] {
+                if other.dns_profile != rule.dns_profile {
+                    if let Some(target) = rule.targets.iter().find(|t| other.targets.contains(t)) {
+                        anyhow::bail!(
+                            "规则 {}: 目标 {} 与规则 {} 共享，dns_profile必须一致",
+                            rule.name,
+                            target,
+                            other.name
+                        );
+                    }
+                }
+            }
+
+            // 验证健康检查配置
+            if let Some(health_check) = &rule.health_check {
+                if let Some(check_type) = &health_check.check_type {
+                    if HealthCheckType::parse(check_type).is_none() {
+                        anyhow::bail!("规则 {}: 不支持的健康检查类型 {}", rule.name, check_type);
+                    }
+                }
+                if let Err(e) = health_check.get_status_range() {
+                    anyhow::bail!("规则 {}: {}", rule.name, e);
+                }
+                if let Err(e) = health_check.validate_udp_probe() {
+                    anyhow::bail!("规则 {}: {}", rule.name, e);
+                }
+            }
         }
 
         Ok(())
     }
 
+    // 对比新旧配置的规则差异，用于热重载
+    pub fn diff_rules(&self, new_config: &Config) -> RuleDiff {
+        let mut diff = RuleDiff::default();
+        // 监听地址变化时，所有保留的规则都需要重新绑定
+        let network_changed = self.network != new_config.network;
+        diff.resolver_changed = self.dns != new_config.dns
+            || self.dns_profiles != new_config.dns_profiles
+            || self.hosts != new_config.hosts;
+        diff.settings_changed = diff.resolver_changed
+            || self.dynamic_update != new_config.dynamic_update
+            || self.drain_timeout != new_config.drain_timeout;
+
+        for old_rule in &self.rules {
+            if !new_config.rules.iter().any(|r| r.name == old_rule.name) {
+                diff.removed.push(old_rule.clone());
+            }
+        }
+
+        for new_rule in &new_config.rules {
+            match self.rules.iter().find(|r| r.name == new_rule.name) {
+                None => diff.added.push(new_rule.clone()),
+                Some(old_rule) => {
+                    if network_changed || !old_rule.same_listener(new_rule) {
+                        diff.restarted.push(new_rule.clone());
+                    } else if old_rule != new_rule {
+                        diff.updated.push(new_rule.clone());
+                    }
+                }
+            }
+        }
+
+        diff
+    }
+
+    pub fn get_drain_timeout(&self) -> u64 {
+        self.drain_timeout.unwrap_or(10)
+    }
+
     // 获取动态更新配置（优化的内置默认值）
     pub fn get_dynamic_update_config(&self) -> DynamicUpdateConfig {
         self.dynamic_update.clone().unwrap_or(DynamicUpdateConfig {
             check_interval: Some(5),     // 5秒健康检查间隔，快速故障检测
             connection_timeout: Some(2), // 2秒连接超时，快速故障检测
+            fall: None,
+            rise: None,
+            failback_delay: None,
+            passive_failures: None,
+            passive_window: None,
         })
     }
 
-    // 获取DNS配置（默认只使用阿里云DNS）
+    // hosts固定解析，键统一为小写且去掉末尾的点
+    pub fn get_hosts(&self) -> HashMap<String, Vec<String>> {
+        self.hosts
+            .iter()
+            .flatten()
+            .map(|(hostname, entry)| {
+                (
+                    hostname.trim_end_matches('.').to_ascii_lowercase(),
+                    entry.addrs(),
+                )
+            })
+            .collect()
+    }
+
+    // 获取DNS配置（默认使用系统DNS配置，读取失败时回退阿里云DNS）
     pub fn get_dns_config(&self) -> DnsConfig {
         self.dns.clone().unwrap_or(DnsConfig {
-            servers: vec![
-                "223.5.5.5:53".to_string(), // 阿里云DNS
-                "223.6.6.6:53".to_string(), // 阿里云DNS备用
-            ],
+            mode: None,
+            servers: Vec::new(),
             timeout: Some(2),  // 2秒超时
             attempts: Some(2), // 重试2次
+            min_ttl: None,
+            max_ttl: None,
+            ip_preference: None,
+            txt_key: None,
         })
     }
 }
@@ -165,6 +744,42 @@ impl DynamicUpdateConfig {
     pub fn get_connection_timeout(&self) -> u64 {
         self.connection_timeout.unwrap_or(2) // 2秒快速故障检测
     }
+
+    pub fn get_fall(&self) -> u32 {
+        self.fall.unwrap_or(1) // 失败1次就标记为不健康，快速切换
+    }
+
+    pub fn get_rise(&self) -> u32 {
+        self.rise.unwrap_or(1)
+    }
+
+    pub fn get_failback_delay(&self) -> u64 {
+        self.failback_delay.unwrap_or(0)
+    }
+
+    pub fn get_passive_failures(&self) -> u32 {
+        self.passive_failures.unwrap_or(0)
+    }
+
+    pub fn get_passive_window(&self) -> u64 {
+        self.passive_window.unwrap_or(10)
+    }
+
+    fn validate(&self, scope: &str) -> Result<()> {
+        if self.check_interval == Some(0) {
+            anyhow::bail!("{}: check_interval必须大于0", scope);
+        }
+        if self.connection_timeout == Some(0) {
+            anyhow::bail!("{}: connection_timeout必须大于0", scope);
+        }
+        if self.fall == Some(0) || self.rise == Some(0) {
+            anyhow::bail!("{}: fall和rise必须大于0", scope);
+        }
+        if self.passive_window == Some(0) {
+            anyhow::bail!("{}: passive_window必须大于0", scope);
+        }
+        Ok(())
+    }
 }
 
 impl ForwardRule {
@@ -195,8 +810,38 @@ impl ForwardRule {
         }
     }
 
-    pub fn get_listen_addr(&self, base_addr: &str) -> String {
-        format!("{}:{}", base_addr, self.listen_port)
+    pub fn get_strategy(&self) -> LoadBalanceStrategy {
+        self.strategy
+            .as_deref()
+            .and_then(LoadBalanceStrategy::parse)
+            .unwrap_or(LoadBalanceStrategy::Priority)
+    }
+
+    // 获取目标权重，未配置时默认为1
+    pub fn get_target_weight(&self, index: usize) -> u32 {
+        self.weights
+            .as_ref()
+            .and_then(|weights| weights.get(index).copied())
+            .unwrap_or(1)
+    }
+
+    pub fn get_connect_retries(&self) -> u32 {
+        self.connect_retries.unwrap_or(0)
+    }
+
+    pub fn get_connect_deadline(&self) -> u64 {
+        self.connect_deadline.unwrap_or(10)
+    }
+
+    pub fn get_masquerade(&self) -> bool {
+        self.masquerade.unwrap_or(true)
+    }
+
+    // 监听端口、协议和缓冲区相同时，可以原地更新目标而不重启监听器
+    pub fn same_listener(&self, other: &ForwardRule) -> bool {
+        self.listen_port == other.listen_port
+            && self.get_protocols() == other.get_protocols()
+            && self.buffer_size == other.buffer_size
     }
 
     // 获取规则级别的动态更新配置
@@ -210,9 +855,207 @@ impl ForwardRule {
                 connection_timeout: rule_config
                     .connection_timeout
                     .or(global_config.connection_timeout),
+                fall: rule_config.fall.or(global_config.fall),
+                rise: rule_config.rise.or(global_config.rise),
+                failback_delay: rule_config.failback_delay.or(global_config.failback_delay),
+                passive_failures: rule_config
+                    .passive_failures
+                    .or(global_config.passive_failures),
+                passive_window: rule_config.passive_window.or(global_config.passive_window),
             }
         } else {
             global_config.clone()
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(name: &str, port: u16, targets: &[&str]) -> ForwardRule {
+        let yaml = format!(
+            "name: {}\nlisten_port: {}\ntargets: [{}]",
+            name,
+            port,
+            targets.join(", ")
+        );
+        serde_yml::from_str(&yaml).unwrap()
+    }
+
+    fn config(rules: Vec<ForwardRule>) -> Config {
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [192.168.1.1]}\n\
+                    rules: []";
+        let mut config: Config = serde_yml::from_str(yaml).unwrap();
+        config.rules = rules;
+        config
+    }
+
+    #[test]
+    fn test_health_check_config() {
+        let check: HealthCheckConfig =
+            serde_yml::from_str("type: https\nexpected_status: \"200-299\"").unwrap();
+        assert_eq!(check.get_type(), HealthCheckType::Https);
+        assert_eq!(check.get_method(), "GET");
+        assert_eq!(check.get_status_range().unwrap(), (200, 299));
+        // 默认校验证书，可显式关闭
+        assert!(check.get_tls_verify());
+        let check: HealthCheckConfig = serde_yml::from_str("type: tls\ntls_verify: false").unwrap();
+        assert!(!check.get_tls_verify());
+
+        let check: HealthCheckConfig = serde_yml::from_str("expected_status: \"204\"").unwrap();
+        assert_eq!(check.get_type(), HealthCheckType::Tcp);
+        assert_eq!(check.get_status_range().unwrap(), (204, 204));
+
+        let check: HealthCheckConfig =
+            serde_yml::from_str("type: udp\npayload_hex: \"de ad\"\nexpect_regex: \"^ok\"")
+                .unwrap();
+        assert_eq!(check.get_payload().unwrap(), Some(vec![0xde, 0xad]));
+        assert!(check.validate_udp_probe().is_ok());
+
+        let check: HealthCheckConfig = serde_yml::from_str("type: udp").unwrap();
+        assert!(check.validate_udp_probe().is_err());
+
+        for invalid in ["abc", "399-200", "99", "200-700"] {
+            let check: HealthCheckConfig =
+                serde_yml::from_str(&format!("expected_status: \"{invalid}\"")).unwrap();
+            assert!(check.get_status_range().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_diff_rules() {
+        let old = config(vec![
+            rule("keep", 80, &["10.0.0.1:80"]),
+            rule("retarget", 443, &["10.0.0.2:443"]),
+            rule("rebind", 3389, &["10.0.0.3:3389"]),
+            rule("gone", 53, &["10.0.0.4:53"]),
+        ]);
+        let new = config(vec![
+            rule("keep", 80, &["10.0.0.1:80"]),
+            rule("retarget", 443, &["10.0.0.2:443", "10.0.0.5:443"]),
+            rule("rebind", 3390, &["10.0.0.3:3389"]),
+            rule("fresh", 8080, &["10.0.0.6:80"]),
+        ]);
+
+        let diff = old.diff_rules(&new);
+        let names = |rules: &[ForwardRule]| -> Vec<String> {
+            rules.iter().map(|r| r.name.clone()).collect()
+        };
+        assert_eq!(names(&diff.added), vec!["fresh"]);
+        assert_eq!(names(&diff.removed), vec!["gone"]);
+        assert_eq!(names(&diff.restarted), vec!["rebind"]);
+        assert_eq!(names(&diff.updated), vec!["retarget"]);
+
+        // 监听地址变化时所有保留的规则都需要重启
+        let mut moved = old.clone();
+        moved.network.listen_addrs = vec!["192.168.1.2".to_string()];
+        let diff = old.diff_rules(&moved);
+        assert_eq!(diff.restarted.len(), 4);
+        assert!(old.diff_rules(&old).is_empty());
+
+        // 只有全局设置变化时规则不变，但重载不能跳过
+        let mut tuned = old.clone();
+        tuned.dynamic_update = Some(DynamicUpdateConfig {
+            check_interval: Some(30),
+            ..old.get_dynamic_update_config()
+        });
+        let diff = old.diff_rules(&tuned);
+        assert!(diff.added.is_empty() && diff.restarted.is_empty() && diff.updated.is_empty());
+        assert!(diff.settings_changed && !diff.resolver_changed);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_rule_names() {
+        let valid = config(vec![
+            rule("web", 80, &["10.0.0.1:80"]),
+            rule("api", 8080, &["10.0.0.2:80"]),
+        ]);
+        assert!(valid.validate().is_ok());
+
+        let duplicate = config(vec![
+            rule("web", 80, &["10.0.0.1:80"]),
+            rule("web", 8080, &["10.0.0.2:80"]),
+        ]);
+        let err = duplicate.validate().unwrap_err().to_string();
+        assert!(err.contains("名称重复"), "{err}");
+    }
+
+    #[test]
+    fn test_hosts_and_dns_profiles() {
+        let yaml = "logging: {level: info, format: text}\n\
+                    network: {listen_addrs: [192.168.1.1]}\n\
+                    hosts: {NAS.lan.: 192.168.1.10, app.lan: [\"192.168.1.11:8080\", \"[fd00::11]:8080\"]}\n\
+                    dns_profiles: {lan: {servers: [\"192.168.1.1:53\"]}}\n\
+                    rules: []";
+        let mut config: Config = serde_yml::from_str(yaml).unwrap();
+        config.rules = vec![rule("internal", 80, &["nas.lan:80"])];
+        config.rules[0].dns_profile = Some("lan".to_string());
+        assert!(config.validate().is_ok());
+
+        let hosts = config.get_hosts();
+        assert_eq!(hosts["nas.lan"], vec!["192.168.1.10"]);
+        assert_eq!(hosts["app.lan"].len(), 2);
+
+        // 未定义的dns_profile
+        let mut invalid = config.clone();
+        invalid.rules[0].dns_profile = Some("wan".to_string());
+        assert!(invalid.validate().is_err());
+
+        // 共享目标的规则dns_profile不一致
+        let mut invalid = config.clone();
+        invalid.rules.push(rule("public", 81, &["nas.lan:80"]));
+        assert!(invalid.validate().is_err());
+
+        // hosts地址必须是IP或IP:PORT
+        let mut invalid = config.clone();
+        invalid.hosts = Some(HashMap::from([(
+            "bad.lan".to_string(),
+            HostsEntry::One("bad.lan".to_string()),
+        )]));
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_masquerade_option() {
+        assert!(rule("rdp", 3389, &["192.168.1.10:3389"]).get_masquerade());
+        let rule: ForwardRule = serde_yml::from_str(
+            "name: rdp\nlisten_port: 3389\nmasquerade: false\ntargets: [\"192.168.1.10:3389\"]",
+        )
+        .unwrap();
+        assert!(!rule.get_masquerade());
+    }
+
+    #[test]
+    fn test_listen_addrs() {
+        let network = NetworkConfig {
+            listen_addrs: vec!["192.168.1.1".to_string(), "[fd00::1]".to_string()],
+        };
+        assert_eq!(network.ips(), vec!["192.168.1.1", "fd00::1"]);
+        assert_eq!(
+            network.socket_addrs(443),
+            vec!["192.168.1.1:443", "[fd00::1]:443"]
+        );
+        assert!(!network.contains_wildcard());
+
+        let network = NetworkConfig {
+            listen_addrs: vec!["::".to_string()],
+        };
+        assert!(network.contains_wildcard());
+        assert_eq!(network.socket_addrs(80), vec!["[::]:80"]);
+
+        // 通配地址不能与其覆盖的地址同时监听
+        let listen = |addrs: &[&str]| {
+            let mut config = config(vec![rule("web", 80, &["10.0.0.1:80"])]);
+            config.network.listen_addrs = addrs.iter().map(|a| a.to_string()).collect();
+            config.validate()
+        };
+        assert!(listen(&["0.0.0.0", "fd00::1"]).is_ok());
+        assert!(listen(&["0.0.0.0", "::"]).is_err());
+        assert!(listen(&["192.168.1.1", "::"]).is_err());
+        assert!(listen(&["0.0.0.0", "192.168.1.1"]).is_err());
+        assert!(listen(&["192.168.1.1", "192.168.1.1"]).is_err());
+    }
+}
@@ -5,12 +5,16 @@ use anyhow::Result;
 use async_trait::async_trait;
 use log::{debug, error, info, warn};
 use std::collections::HashMap;
-use std::process::Command;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Command, Stdio};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use crate::common::CommonManager;
-use crate::config::Config;
+use crate::config::{is_wildcard, Config, ForwardRule, LoadBalanceStrategy, RuleDiff};
+#[cfg(target_os = "linux")]
+use crate::nft_netlink::{self, Batch, Expr};
 
 // ================================
 // 防火墙后端枚举
@@ -18,6 +22,7 @@ use crate::config::Config;
 #[derive(Debug, Clone, PartialEq)]
 pub enum FirewallBackend {
     Nftables,
+    NftablesNetlink, // 通过netlink直接操作nf_tables，不需要nft命令
     Iptables,
     Pfctl, // macOS pfctl防火墙
 }
@@ -35,7 +40,7 @@ pub enum ForwardType {
 // ================================
 // 防火墙规则结构
 // ================================
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 #[allow(dead_code)]
 pub struct FirewallRule {
     pub rule_id: String,
@@ -90,6 +95,8 @@ pub trait FirewallManager: Send + Sync {
     async fn list_rules(&self) -> Result<Vec<FirewallRule>>;
     async fn is_rule_exists(&self, rule_id: &str) -> Result<bool>;
     async fn rebuild_all_rules(&mut self, rules: &[FirewallRule]) -> Result<()>;
+    // 热重载时更新DNAT匹配的监听地址，下次提交规则时生效
+    fn set_listen_addrs(&mut self, _listen_addrs: Vec<String>) {}
 }
 
 // ================================
@@ -256,6 +263,61 @@ impl FirewallManager for PfctlManager {
     }
 }
 
+// DNAT规则的目标地址匹配：每个监听地址一条，包含通配地址时不限制 (None)
+fn daddr_matches(listen_addrs: &[String]) -> Vec<Option<IpAddr>> {
+    if listen_addrs.is_empty() || listen_addrs.iter().any(|ip| is_wildcard(ip)) {
+        return vec![None];
+    }
+    listen_addrs
+        .iter()
+        .filter_map(|ip| ip.parse::<IpAddr>().ok())
+        .map(Some)
+        .collect()
+}
+
+// 启用的规则按配置顺序排列，保证生成的规则集稳定
+fn ordered_rules(rules: &[FirewallRule]) -> Vec<&FirewallRule> {
+    let mut rules: Vec<&FirewallRule> = rules.iter().filter(|rule| rule.enabled).collect();
+    rules.sort_by(|a, b| (a.config_index, &a.rule_id).cmp(&(b.config_index, &b.rule_id)));
+    rules
+}
+
+// 通过标准输入把整个规则脚本交给 nft -f / iptables-restore，命令失败时返回stderr
+fn run_with_stdin(program: &str, args: &[&str], input: &str) -> Result<()> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow::anyhow!("{}", stderr.trim()));
+    }
+    Ok(())
+}
+
+// 规则的目标IP和端口，目标未写端口时使用监听端口
+fn target_socket_addr(rule: &FirewallRule) -> Option<SocketAddr> {
+    let target = rule.target_addr.parse::<SocketAddr>().ok().or_else(|| {
+        rule.target_addr
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, rule.listen_port))
+    });
+    if target.is_none() {
+        warn!("无法解析目标地址 {}，跳过该规则", rule.target_addr);
+    }
+    target
+}
+
 // ================================
 // nftables 管理器 - 针对Firewall4优化
 // ================================
@@ -263,17 +325,17 @@ pub struct NftablesManager {
     table_name: String,
     chain_prerouting: String,
     chain_postrouting: String,
-    listen_addr: String,
+    listen_addrs: Vec<String>,
     rules: HashMap<String, FirewallRule>,
 }
 
 impl NftablesManager {
-    pub fn new(listen_addr: String) -> Self {
+    pub fn new(listen_addrs: Vec<String>) -> Self {
         Self {
             table_name: "smart_forward".to_string(),
             chain_prerouting: "prerouting".to_string(),
             chain_postrouting: "postrouting".to_string(),
-            listen_addr,
+            listen_addrs,
             rules: HashMap::new(),
         }
     }
@@ -289,6 +351,61 @@ impl NftablesManager {
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
+    // 通过 nft -f 一次性提交整个脚本，nft保证脚本在单个事务中生效，失败时原规则集不变
+    async fn apply_ruleset(&self, script: &str) -> Result<()> {
+        run_with_stdin("nft", &["-f", "-"], script)
+            .map_err(|e| anyhow::anyhow!("nft规则集提交失败: {}", e))
+    }
+
+    // 生成完整的smart_forward表：先删除旧表再重建，整个脚本在同一个事务中执行
+    // 开头的空表声明保证表不存在时delete也不会失败
+    fn render_ruleset(&self, rules: &[FirewallRule]) -> String {
+        let table = format!("inet {}", self.table_name);
+        let mut script = vec![
+            format!("table {table}"),
+            format!("delete table {table}"),
+            format!("table {table} {{"),
+            // prerouting优先级-150，高于Firewall4默认DNAT(-100)
+            format!(
+                "    chain {} {{ type nat hook prerouting priority -150; }}",
+                self.chain_prerouting
+            ),
+            // postrouting优先级50，低于默认SNAT(100)但足够用
+            format!(
+                "    chain {} {{ type nat hook postrouting priority 50; }}",
+                self.chain_postrouting
+            ),
+            "}".to_string(),
+        ];
+
+        for rule in ordered_rules(rules) {
+            let (chain, statements) = match rule.forward_type {
+                ForwardType::DNAT => (&self.chain_prerouting, self.generate_dnat_rules(rule)),
+                ForwardType::SNAT => (
+                    &self.chain_postrouting,
+                    self.generate_snat_rule(rule).into_iter().collect(),
+                ),
+            };
+            for statement in statements {
+                // 多个规则共用同一目标时SNAT语句相同，只保留一条
+                let line = format!("add rule {table} {chain} {statement}");
+                if !script.contains(&line) {
+                    script.push(line);
+                }
+            }
+        }
+
+        script.join("\n") + "\n"
+    }
+
+    // 提交规则集，成功后才更新内存中的规则
+    async fn commit(&mut self, rules: HashMap<String, FirewallRule>) -> Result<()> {
+        let all_rules: Vec<FirewallRule> = rules.values().cloned().collect();
+        self.apply_ruleset(&self.render_ruleset(&all_rules)).await?;
+        self.rules = rules;
+        Ok(())
+    }
+
     async fn table_exists(&self) -> Result<bool> {
         match self
             .execute_nft(&["list", "table", "inet", &self.table_name])
@@ -299,57 +416,6 @@ impl NftablesManager {
         }
     }
 
-    async fn create_table_and_chains(&self) -> Result<()> {
-        info!("创建nftables表和链，优先级高于Firewall4默认规则");
-
-        // 创建专用table
-        self.execute_nft(&["add", "table", "inet", &self.table_name])
-            .await?;
-        debug!("创建table: {}", self.table_name);
-
-        // 创建prerouting chain - 优先级-150，高于Firewall4默认DNAT(-100)
-        self.execute_nft(&[
-            "add",
-            "chain",
-            "inet",
-            &self.table_name,
-            &self.chain_prerouting,
-            "{",
-            "type",
-            "nat",
-            "hook",
-            "prerouting",
-            "priority",
-            "-150",
-            ";",
-            "}",
-        ])
-        .await?;
-        info!("创建prerouting链，优先级-150（高于Firewall4默认-100）");
-
-        // 创建postrouting chain - 优先级50，低于默认SNAT(100)但足够用
-        self.execute_nft(&[
-            "add",
-            "chain",
-            "inet",
-            &self.table_name,
-            &self.chain_postrouting,
-            "{",
-            "type",
-            "nat",
-            "hook",
-            "postrouting",
-            "priority",
-            "50",
-            ";",
-            "}",
-        ])
-        .await?;
-        info!("创建postrouting链，优先级50");
-
-        Ok(())
-    }
-
     fn detect_ip_version(target_addr: &str) -> &'static str {
         let target_parts: Vec<&str> = target_addr.split(':').collect();
         let target_ip = target_parts[0];
@@ -365,7 +431,8 @@ impl NftablesManager {
         }
     }
 
-    fn generate_dnat_rule(&self, rule: &FirewallRule) -> Vec<String> {
+    // 每个监听地址生成一条DNAT规则语句
+    fn generate_dnat_rules(&self, rule: &FirewallRule) -> Vec<String> {
         let target_parts: Vec<&str> = rule.target_addr.split(':').collect();
         if target_parts.is_empty() {
             // 防止空地址导致panic
@@ -394,51 +461,60 @@ impl NftablesManager {
             format!("{}:{}", target_ip, target_port)
         };
 
-        // DNAT规则生成：只有指定具体监听地址时才添加地址限制
-        let mut rule_args = vec![
-            "add".to_string(),
-            "rule".to_string(),
-            "inet".to_string(),
-            self.table_name.clone(),
-            self.chain_prerouting.clone(),
-        ];
+        let mut rules = Vec::new();
+        for daddr in daddr_matches(&self.listen_addrs) {
+            // DNAT规则生成：只有指定具体监听地址时才添加地址限制
+            let mut rule_args = Vec::new();
+
+            if let Some(daddr) = daddr {
+                // 监听地址与目标地址族不同时无法DNAT，跳过
+                let family = if daddr.is_ipv6() { "ip6" } else { "ip" };
+                if family != ip_version {
+                    debug!(
+                        "监听地址 {} 与目标 {} 地址族不同，跳过",
+                        daddr, rule.target_addr
+                    );
+                    continue;
+                }
+                rule_args.extend(vec![
+                    family.to_string(),
+                    "daddr".to_string(),
+                    daddr.to_string(),
+                ]);
+            }
 
-        // 只有当监听地址不是0.0.0.0时才添加目标地址限制
-        // 0.0.0.0在路由系统中不推荐使用，但保持兼容性
-        if self.listen_addr != "0.0.0.0" {
             rule_args.extend(vec![
-                "ip".to_string(),
-                "daddr".to_string(),
-                self.listen_addr.clone(),
+                rule.protocol.clone(),
+                "dport".to_string(),
+                rule.listen_port.to_string(),
+                "dnat".to_string(),
+                ip_version.to_string(),
+                "to".to_string(),
+                formatted_target.clone(),
             ]);
+            rules.push(rule_args.join(" "));
         }
 
-        rule_args.extend(vec![
-            rule.protocol.clone(),
-            "dport".to_string(),
-            rule.listen_port.to_string(),
-            "dnat".to_string(),
-            ip_version.to_string(),
-            "to".to_string(),
-            formatted_target,
-        ]);
-
-        rule_args
+        if rules.is_empty() {
+            warn!(
+                "没有与目标 {} 地址族相同的监听地址，未生成DNAT规则",
+                rule.target_addr
+            );
+        }
+        rules
     }
 
-    fn generate_snat_rule(&self, _rule: &FirewallRule) -> Vec<String> {
-        // 对于转发到外网的流量，添加SNAT规则
-        vec![
-            "add".to_string(),
-            "rule".to_string(),
-            "inet".to_string(),
-            self.table_name.clone(),
-            self.chain_postrouting.clone(),
-            "oifname".to_string(),
-            "!=".to_string(),
-            "\"lo\"".to_string(),
-            "masquerade".to_string(),
-        ]
+    // 只对经过DNAT且发往该规则目标的连接做masquerade，不影响其他出站流量
+    fn generate_snat_rule(&self, rule: &FirewallRule) -> Option<String> {
+        let target = target_socket_addr(rule)?;
+        let family = if target.is_ipv6() { "ip6" } else { "ip" };
+        Some(format!(
+            "ct status dnat {} daddr {} {} dport {} masquerade",
+            family,
+            target.ip(),
+            rule.protocol,
+            target.port()
+        ))
     }
 }
 
@@ -462,16 +538,8 @@ impl FirewallManager for NftablesManager {
             }
         }
 
-        // 如果表已存在，先清理
-        if self.table_exists().await? {
-            warn!("检测到已存在的smart_forward表，正在清理...");
-            let _ = self
-                .execute_nft(&["delete", "table", "inet", &self.table_name])
-                .await;
-        }
-
-        // 创建表和链
-        self.create_table_and_chains().await?;
+        // 用空规则集替换可能残留的smart_forward表
+        self.commit(HashMap::new()).await?;
 
         info!("nftables管理器初始化完成，已设置高优先级规则");
         Ok(())
@@ -480,39 +548,24 @@ impl FirewallManager for NftablesManager {
     async fn add_forward_rule(&mut self, rule: &FirewallRule) -> Result<()> {
         debug!("添加转发规则: {} -> {}", rule.listen_port, rule.target_addr);
 
-        // 添加DNAT规则
-        if rule.forward_type == ForwardType::DNAT {
-            let dnat_strings = self.generate_dnat_rule(rule);
-            let dnat_args: Vec<&str> = dnat_strings.iter().map(|s| s.as_str()).collect();
-            self.execute_nft(&dnat_args).await?;
-            debug!(
-                "添加DNAT规则: {}:{} -> {}",
-                rule.protocol, rule.listen_port, rule.target_addr
-            );
-        }
-
-        // 添加SNAT规则（masquerade）
-        if rule.forward_type == ForwardType::SNAT {
-            let snat_strings = self.generate_snat_rule(rule);
-            let snat_args: Vec<&str> = snat_strings.iter().map(|s| s.as_str()).collect();
-            self.execute_nft(&snat_args).await?;
-            debug!("添加SNAT规则（masquerade）");
-        }
-
-        // 保存规则到内存
-        self.rules.insert(rule.rule_id.clone(), rule.clone());
+        let mut rules = self.rules.clone();
+        rules.insert(rule.rule_id.clone(), rule.clone());
+        self.commit(rules).await?;
+        debug!(
+            "添加{:?}规则: {}:{} -> {}",
+            rule.forward_type, rule.protocol, rule.listen_port, rule.target_addr
+        );
 
         Ok(())
     }
 
     async fn remove_forward_rule(&mut self, rule_id: &str) -> Result<()> {
-        if let Some(_rule) = self.rules.get(rule_id) {
+        if self.rules.contains_key(rule_id) {
             debug!("删除转发规则: {}", rule_id);
 
-            // 由于nftables的规则删除比较复杂，我们采用重建策略
-            self.rules.remove(rule_id);
-            let remaining_rules: Vec<FirewallRule> = self.rules.values().cloned().collect();
-            self.rebuild_all_rules(&remaining_rules).await?;
+            let mut rules = self.rules.clone();
+            rules.remove(rule_id);
+            self.commit(rules).await?;
         }
 
         Ok(())
@@ -521,12 +574,10 @@ impl FirewallManager for NftablesManager {
     async fn update_forward_rule(&mut self, rule: &FirewallRule) -> Result<()> {
         debug!("更新转发规则: {} -> {}", rule.listen_port, rule.target_addr);
 
-        // 更新内存中的规则
-        self.rules.insert(rule.rule_id.clone(), rule.clone());
-
-        // 重建所有规则以确保一致性
-        let all_rules: Vec<FirewallRule> = self.rules.values().cloned().collect();
-        self.rebuild_all_rules(&all_rules).await?;
+        // 整表原子替换，切换目标时不存在没有DNAT规则的窗口
+        let mut rules = self.rules.clone();
+        rules.insert(rule.rule_id.clone(), rule.clone());
+        self.commit(rules).await?;
 
         Ok(())
     }
@@ -558,35 +609,237 @@ impl FirewallManager for NftablesManager {
     async fn rebuild_all_rules(&mut self, rules: &[FirewallRule]) -> Result<()> {
         debug!("重建所有nftables规则，共{}条", rules.len());
 
-        // 清空现有规则（保留表和链结构）
-        if self.table_exists().await? {
-            self.execute_nft(&["flush", "table", "inet", &self.table_name])
-                .await?;
-        } else {
-            // 如果表不存在，重新创建
-            self.create_table_and_chains().await?;
+        let rules = rules
+            .iter()
+            .map(|rule| (rule.rule_id.clone(), rule.clone()))
+            .collect();
+        self.commit(rules).await?;
+
+        debug!("规则重建完成");
+        Ok(())
+    }
+
+    fn set_listen_addrs(&mut self, listen_addrs: Vec<String>) {
+        self.listen_addrs = listen_addrs;
+    }
+}
+
+// ================================
+// nftables netlink 管理器 - 不依赖nft命令
+// ================================
+#[cfg(target_os = "linux")]
+pub struct NftablesNetlinkManager {
+    table_name: String,
+    chain_prerouting: String,
+    chain_postrouting: String,
+    listen_addrs: Vec<String>,
+    rules: HashMap<String, FirewallRule>,
+}
+
+#[cfg(target_os = "linux")]
+impl NftablesNetlinkManager {
+    pub fn new(listen_addrs: Vec<String>) -> Self {
+        Self {
+            table_name: "smart_forward".to_string(),
+            chain_prerouting: "prerouting".to_string(),
+            chain_postrouting: "postrouting".to_string(),
+            listen_addrs,
+            rules: HashMap::new(),
         }
+    }
 
-        // 重新添加所有规则
-        for rule in rules {
-            if rule.enabled {
-                // 直接添加规则，不更新内存（避免递归调用）
-                if rule.forward_type == ForwardType::DNAT {
-                    let dnat_strings = self.generate_dnat_rule(rule);
-                    let dnat_args: Vec<&str> = dnat_strings.iter().map(|s| s.as_str()).collect();
-                    self.execute_nft(&dnat_args).await?;
+    // 每个监听地址生成一条DNAT规则，与nft命令后端的规则相同
+    fn dnat_exprs(&self, rule: &FirewallRule) -> Vec<Vec<Expr>> {
+        let (Some(target), Some(l4proto)) = (
+            target_socket_addr(rule),
+            nft_netlink::l4proto(&rule.protocol),
+        ) else {
+            return vec![];
+        };
+
+        let mut rules = Vec::new();
+        for daddr in daddr_matches(&self.listen_addrs) {
+            let mut exprs = nft_netlink::match_nfproto(target.is_ipv6());
+            if let Some(daddr) = daddr {
+                // 监听地址与目标地址族不同时无法DNAT，跳过
+                if daddr.is_ipv6() != target.is_ipv6() {
+                    continue;
                 }
-                if rule.forward_type == ForwardType::SNAT {
-                    let snat_strings = self.generate_snat_rule(rule);
-                    let snat_args: Vec<&str> = snat_strings.iter().map(|s| s.as_str()).collect();
-                    self.execute_nft(&snat_args).await?;
+                exprs.extend(nft_netlink::match_daddr(daddr));
+            }
+            exprs.extend(nft_netlink::match_l4_dport(l4proto, rule.listen_port));
+            exprs.extend(nft_netlink::dnat(target));
+            rules.push(exprs);
+        }
+
+        if rules.is_empty() {
+            warn!(
+                "没有与目标 {} 地址族相同的监听地址，未生成DNAT规则",
+                rule.target_addr
+            );
+        }
+        rules
+    }
+
+    // 只对经过DNAT且发往该规则目标的连接做masquerade
+    fn snat_exprs(&self, rule: &FirewallRule) -> Option<Vec<Expr>> {
+        let target = target_socket_addr(rule)?;
+        let l4proto = nft_netlink::l4proto(&rule.protocol)?;
+
+        let mut exprs = nft_netlink::match_ct_dnat();
+        exprs.extend(nft_netlink::match_nfproto(target.is_ipv6()));
+        exprs.extend(nft_netlink::match_daddr(target.ip()));
+        exprs.extend(nft_netlink::match_l4_dport(l4proto, target.port()));
+        exprs.extend(nft_netlink::masquerade());
+        Some(exprs)
+    }
+
+    // 与 nft -f 脚本相同：先声明再删除旧表，然后重建表、链和所有规则，整批一个事务
+    fn build_batch(&self, rules: &[FirewallRule]) -> Batch {
+        let mut batch = Batch::new();
+        batch.add_table(&self.table_name);
+        batch.del_table(&self.table_name);
+        batch.add_table(&self.table_name);
+        // prerouting优先级-150，高于Firewall4默认DNAT(-100)；postrouting优先级50
+        batch.add_nat_chain(
+            &self.table_name,
+            &self.chain_prerouting,
+            nft_netlink::Hook::Prerouting,
+            -150,
+        );
+        batch.add_nat_chain(
+            &self.table_name,
+            &self.chain_postrouting,
+            nft_netlink::Hook::Postrouting,
+            50,
+        );
+
+        let mut added_snat = Vec::new();
+        for rule in ordered_rules(rules) {
+            match rule.forward_type {
+                ForwardType::DNAT => {
+                    for exprs in self.dnat_exprs(rule) {
+                        batch.add_rule(&self.table_name, &self.chain_prerouting, &exprs);
+                    }
+                }
+                ForwardType::SNAT => {
+                    // 多个规则共用同一目标时SNAT规则相同，只保留一条
+                    if let Some(exprs) = self.snat_exprs(rule) {
+                        if !added_snat.contains(&exprs) {
+                            batch.add_rule(&self.table_name, &self.chain_postrouting, &exprs);
+                            added_snat.push(exprs);
+                        }
+                    }
                 }
             }
         }
+        batch
+    }
+
+    // netlink调用是阻塞的，放到阻塞线程池执行；提交成功后才更新内存中的规则
+    async fn commit(&mut self, rules: HashMap<String, FirewallRule>) -> Result<()> {
+        let all_rules: Vec<FirewallRule> = rules.values().cloned().collect();
+        let batch = self.build_batch(&all_rules);
+        tokio::task::spawn_blocking(move || nft_netlink::send_batch(batch)).await??;
+        self.rules = rules;
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[async_trait]
+impl FirewallManager for NftablesNetlinkManager {
+    async fn initialize(&mut self) -> Result<()> {
+        info!("初始化nftables netlink管理器（不依赖nft命令）");
+
+        // 用空规则集替换可能残留的smart_forward表，同时确认内核支持nf_tables
+        self.commit(HashMap::new()).await.map_err(|e| {
+            anyhow::anyhow!(
+                "nf_tables netlink不可用: {}\n💡 解决方法:\n   1. 使用管理员权限运行: sudo ./smart-forward\n   2. 或使用用户态转发: ./smart-forward --user-mode",
+                e
+            )
+        })?;
+
+        info!("nftables netlink管理器初始化完成，已设置高优先级规则");
+        Ok(())
+    }
+
+    async fn add_forward_rule(&mut self, rule: &FirewallRule) -> Result<()> {
+        debug!("添加转发规则: {} -> {}", rule.listen_port, rule.target_addr);
+
+        let mut rules = self.rules.clone();
+        rules.insert(rule.rule_id.clone(), rule.clone());
+        self.commit(rules).await?;
+        debug!(
+            "添加{:?}规则: {}:{} -> {}",
+            rule.forward_type, rule.protocol, rule.listen_port, rule.target_addr
+        );
+
+        Ok(())
+    }
+
+    async fn remove_forward_rule(&mut self, rule_id: &str) -> Result<()> {
+        if self.rules.contains_key(rule_id) {
+            debug!("删除转发规则: {}", rule_id);
+
+            let mut rules = self.rules.clone();
+            rules.remove(rule_id);
+            self.commit(rules).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn update_forward_rule(&mut self, rule: &FirewallRule) -> Result<()> {
+        debug!("更新转发规则: {} -> {}", rule.listen_port, rule.target_addr);
+
+        let mut rules = self.rules.clone();
+        rules.insert(rule.rule_id.clone(), rule.clone());
+        self.commit(rules).await?;
+
+        Ok(())
+    }
+
+    async fn clear_all_rules(&mut self) -> Result<()> {
+        info!("清理所有smart_forward规则");
+
+        // 先声明再删除，表不存在时也不会失败
+        let mut batch = Batch::new();
+        batch.add_table(&self.table_name);
+        batch.del_table(&self.table_name);
+        tokio::task::spawn_blocking(move || nft_netlink::send_batch(batch)).await??;
+        info!("已删除smart_forward表");
+
+        // 清空内存中的规则
+        self.rules.clear();
+
+        Ok(())
+    }
+
+    async fn list_rules(&self) -> Result<Vec<FirewallRule>> {
+        Ok(self.rules.values().cloned().collect())
+    }
+
+    async fn is_rule_exists(&self, rule_id: &str) -> Result<bool> {
+        Ok(self.rules.contains_key(rule_id))
+    }
+
+    async fn rebuild_all_rules(&mut self, rules: &[FirewallRule]) -> Result<()> {
+        debug!("重建所有nftables规则，共{}条", rules.len());
+
+        let rules = rules
+            .iter()
+            .map(|rule| (rule.rule_id.clone(), rule.clone()))
+            .collect();
+        self.commit(rules).await?;
 
         debug!("规则重建完成");
         Ok(())
     }
+
+    fn set_listen_addrs(&mut self, listen_addrs: Vec<String>) {
+        self.listen_addrs = listen_addrs;
+    }
 }
 
 // ================================
@@ -595,146 +848,280 @@ impl FirewallManager for NftablesManager {
 pub struct IptablesManager {
     chain_prerouting: String,
     chain_postrouting: String,
-    listen_addr: String,
+    listen_addrs: Vec<String>,
+    ipv6_enabled: bool,     // ip6tables可用且支持nat表时才处理IPv6规则
+    restore_prefix: String, // iptables-restore/ip6tables-restore命令所在目录前缀，默认从PATH查找
     rules: HashMap<String, FirewallRule>,
 }
 
 impl IptablesManager {
-    pub fn new(listen_addr: String) -> Self {
+    pub fn new(listen_addrs: Vec<String>) -> Self {
         Self {
             chain_prerouting: "SMART_FORWARD_PREROUTING".to_string(),
             chain_postrouting: "SMART_FORWARD_POSTROUTING".to_string(),
-            listen_addr,
+            listen_addrs,
+            ipv6_enabled: false,
+            restore_prefix: String::new(),
             rules: HashMap::new(),
         }
     }
 
-    async fn execute_iptables(&self, args: &[&str]) -> Result<String> {
-        let output = Command::new("iptables").args(args).output()?;
+    // 按目标地址族选择命令：IPv4使用iptables，IPv6使用ip6tables
+    fn binary_for(target: &SocketAddr) -> &'static str {
+        if target.is_ipv6() {
+            "ip6tables"
+        } else {
+            "iptables"
+        }
+    }
+
+    // 当前启用的地址族命令
+    fn binaries(&self) -> Vec<&'static str> {
+        if self.ipv6_enabled {
+            vec!["iptables", "ip6tables"]
+        } else {
+            vec!["iptables"]
+        }
+    }
+
+    async fn execute_iptables(&self, binary: &str, args: &[&str]) -> Result<String> {
+        let output = Command::new(binary).args(args).output()?;
 
         if !output.status.success() {
             let stderr = String::from_utf8_lossy(&output.stderr);
-            return Err(anyhow::anyhow!("iptables命令执行失败: {}", stderr));
+            return Err(anyhow::anyhow!("{}命令执行失败: {}", binary, stderr));
         }
 
         Ok(String::from_utf8_lossy(&output.stdout).to_string())
     }
 
-    async fn chain_exists(&self, table: &str, chain: &str) -> Result<bool> {
-        match self.execute_iptables(&["-t", table, "-L", chain]).await {
+    async fn chain_exists(&self, binary: &str, table: &str, chain: &str) -> Result<bool> {
+        match self
+            .execute_iptables(binary, &["-t", table, "-L", chain])
+            .await
+        {
             Ok(_) => Ok(true),
             Err(_) => Ok(false),
         }
     }
 
-    async fn create_chains(&self) -> Result<()> {
-        info!("创建iptables链，优先级高于默认规则");
+    async fn create_chains(&self, binary: &str) -> Result<()> {
+        info!("创建{}链，优先级高于默认规则", binary);
 
         // 创建PREROUTING链
-        if !self.chain_exists("nat", &self.chain_prerouting).await? {
-            self.execute_iptables(&["-t", "nat", "-N", &self.chain_prerouting])
+        if !self
+            .chain_exists(binary, "nat", &self.chain_prerouting)
+            .await?
+        {
+            self.execute_iptables(binary, &["-t", "nat", "-N", &self.chain_prerouting])
                 .await?;
-            debug!("创建链: {}", self.chain_prerouting);
+            debug!("创建链: {} {}", binary, self.chain_prerouting);
         }
 
         // 创建POSTROUTING链
-        if !self.chain_exists("nat", &self.chain_postrouting).await? {
-            self.execute_iptables(&["-t", "nat", "-N", &self.chain_postrouting])
+        if !self
+            .chain_exists(binary, "nat", &self.chain_postrouting)
+            .await?
+        {
+            self.execute_iptables(binary, &["-t", "nat", "-N", &self.chain_postrouting])
                 .await?;
-            debug!("创建链: {}", self.chain_postrouting);
+            debug!("创建链: {} {}", binary, self.chain_postrouting);
         }
 
         // 将自定义链插入到主链的开头（高优先级）
         // 检查是否已经插入，避免重复
         let prerouting_check = self
-            .execute_iptables(&["-t", "nat", "-L", "PREROUTING", "--line-numbers"])
+            .execute_iptables(binary, &["-t", "nat", "-L", "PREROUTING", "--line-numbers"])
             .await?;
         if !prerouting_check.contains(&self.chain_prerouting) {
-            self.execute_iptables(&[
-                "-t",
-                "nat",
-                "-I",
-                "PREROUTING",
-                "1",
-                "-j",
-                &self.chain_prerouting,
-            ])
+            self.execute_iptables(
+                binary,
+                &[
+                    "-t",
+                    "nat",
+                    "-I",
+                    "PREROUTING",
+                    "1",
+                    "-j",
+                    &self.chain_prerouting,
+                ],
+            )
             .await?;
-            info!("插入PREROUTING链到位置1（最高优先级）");
+            info!("插入{} PREROUTING链到位置1（最高优先级）", binary);
         }
 
         let postrouting_check = self
-            .execute_iptables(&["-t", "nat", "-L", "POSTROUTING", "--line-numbers"])
+            .execute_iptables(
+                binary,
+                &["-t", "nat", "-L", "POSTROUTING", "--line-numbers"],
+            )
             .await?;
         if !postrouting_check.contains(&self.chain_postrouting) {
-            self.execute_iptables(&[
-                "-t",
-                "nat",
-                "-I",
-                "POSTROUTING",
-                "1",
-                "-j",
-                &self.chain_postrouting,
-            ])
+            self.execute_iptables(
+                binary,
+                &[
+                    "-t",
+                    "nat",
+                    "-I",
+                    "POSTROUTING",
+                    "1",
+                    "-j",
+                    &self.chain_postrouting,
+                ],
+            )
             .await?;
-            info!("插入POSTROUTING链到位置1");
+            info!("插入{} POSTROUTING链到位置1", binary);
         }
 
         Ok(())
     }
 
-    fn generate_dnat_args(&self, rule: &FirewallRule) -> Vec<String> {
-        let target_parts: Vec<&str> = rule.target_addr.split(':').collect();
-        if target_parts.is_empty() {
-            // 防止空地址导致panic
-            error!("目标地址为空: {}", rule.target_addr);
-            return vec![];
+    // 每个监听地址生成一条DNAT规则，返回使用的命令和参数
+    fn generate_dnat_args(&self, rule: &FirewallRule) -> Option<(&'static str, Vec<Vec<String>>)> {
+        let target = target_socket_addr(rule)?;
+
+        let mut rules = Vec::new();
+        for daddr in daddr_matches(&self.listen_addrs) {
+            // DNAT规则生成：只有指定具体监听地址时才添加地址限制
+            let mut rule_args = vec!["-A".to_string(), self.chain_prerouting.clone()];
+
+            if let Some(daddr) = daddr {
+                // 监听地址与目标地址族不同时无法DNAT，跳过
+                if daddr.is_ipv6() != target.is_ipv6() {
+                    debug!(
+                        "监听地址 {} 与目标 {} 地址族不同，跳过",
+                        daddr, rule.target_addr
+                    );
+                    continue;
+                }
+                rule_args.extend(vec!["-d".to_string(), daddr.to_string()]);
+            }
+
+            rule_args.extend(vec![
+                "-p".to_string(),
+                rule.protocol.clone(),
+                "--dport".to_string(),
+                rule.listen_port.to_string(),
+                "-j".to_string(),
+                "DNAT".to_string(),
+                "--to-destination".to_string(),
+                // IPv6目标格式为 [v6]:port
+                target.to_string(),
+            ]);
+            rules.push(rule_args);
         }
-        let target_ip = target_parts[0];
-        let port_str = rule.listen_port.to_string();
-        let port_str_ref = port_str.as_str();
-        let target_port = target_parts.get(1).unwrap_or(&port_str_ref);
 
-        // DNAT规则生成：只有指定具体监听地址时才添加地址限制
-        let mut rule_args = vec![
-            "-t".to_string(),
-            "nat".to_string(),
-            "-A".to_string(),
-            self.chain_prerouting.clone(),
+        if rules.is_empty() {
+            warn!(
+                "没有与目标 {} 地址族相同的监听地址，未生成DNAT规则",
+                rule.target_addr
+            );
+        }
+        Some((Self::binary_for(&target), rules))
+    }
+
+    // 只对经过DNAT且发往该规则目标的连接做masquerade，不影响其他出站流量
+    fn generate_snat_args(&self, rule: &FirewallRule) -> Option<(&'static str, Vec<String>)> {
+        let target = target_socket_addr(rule)?;
+        Some((
+            Self::binary_for(&target),
+            vec![
+                "-A".to_string(),
+                self.chain_postrouting.clone(),
+                "-p".to_string(),
+                rule.protocol.clone(),
+                "-d".to_string(),
+                target.ip().to_string(),
+                "--dport".to_string(),
+                target.port().to_string(),
+                "-m".to_string(),
+                "conntrack".to_string(),
+                "--ctstate".to_string(),
+                "DNAT".to_string(),
+                "-j".to_string(),
+                "MASQUERADE".to_string(),
+            ],
+        ))
+    }
+
+    // 生成一个地址族的 iptables-restore 内容，只包含nat表的两条自定义链
+    // 配合 --noflush 使用：声明链时清空并重建这两条链，其他链保持不变，整个nat表一次提交
+    fn render_restore(&self, binary: &str, rules: &[FirewallRule]) -> String {
+        let mut lines = vec![
+            "*nat".to_string(),
+            format!(":{} - [0:0]", self.chain_prerouting),
+            format!(":{} - [0:0]", self.chain_postrouting),
         ];
 
-        // 只有当监听地址不是0.0.0.0时才添加目标地址限制
-        // 0.0.0.0在路由系统中不推荐使用，但保持兼容性
-        if self.listen_addr != "0.0.0.0" {
-            rule_args.extend(vec!["-d".to_string(), self.listen_addr.clone()]);
+        for rule in ordered_rules(rules) {
+            let generated = match rule.forward_type {
+                ForwardType::DNAT => self.generate_dnat_args(rule),
+                ForwardType::SNAT => self
+                    .generate_snat_args(rule)
+                    .map(|(binary, args)| (binary, vec![args])),
+            };
+            let Some((rule_binary, rule_lines)) = generated else {
+                continue;
+            };
+            if rule_binary != binary {
+                continue;
+            }
+            for args in rule_lines {
+                // 多个规则共用同一目标时SNAT规则相同，只保留一条
+                let line = args.join(" ");
+                if !lines.contains(&line) {
+                    lines.push(line);
+                }
+            }
         }
 
-        rule_args.extend(vec![
-            "-p".to_string(),
-            rule.protocol.clone(),
-            "--dport".to_string(),
-            rule.listen_port.to_string(),
-            "-j".to_string(),
-            "DNAT".to_string(),
-            "--to-destination".to_string(),
-            format!("{}:{}", target_ip, target_port),
-        ]);
+        lines.push("COMMIT".to_string());
+        lines.join("\n") + "\n"
+    }
 
-        rule_args
+    async fn apply_restore(&self, binary: &str, payload: &str) -> Result<()> {
+        let program = format!("{}{binary}-restore", self.restore_prefix);
+        run_with_stdin(&program, &["--noflush"], payload)
+            .map_err(|e| anyhow::anyhow!("{}提交失败: {}", program, e))
     }
 
-    fn generate_snat_args(&self) -> Vec<String> {
-        vec![
-            "-t".to_string(),
-            "nat".to_string(),
-            "-A".to_string(),
-            self.chain_postrouting.clone(),
-            "!".to_string(),
-            "-o".to_string(),
-            "lo".to_string(),
-            "-j".to_string(),
-            "MASQUERADE".to_string(),
-        ]
+    // 每个地址族提交一次，任一地址族失败时所有地址族保持原内容，成功后才更新内存中的规则
+    async fn commit(&mut self, rules: HashMap<String, FirewallRule>) -> Result<()> {
+        let all_rules: Vec<FirewallRule> = rules.values().cloned().collect();
+
+        if !self.ipv6_enabled {
+            let ipv6_rules = all_rules
+                .iter()
+                .filter_map(target_socket_addr)
+                .filter(|target| target.is_ipv6())
+                .count();
+            if ipv6_rules > 0 {
+                warn!("ip6tables不可用，跳过{}条IPv6规则", ipv6_rules);
+            }
+        }
+
+        // 先生成所有地址族的内容，再依次提交
+        let payloads: Vec<(&str, String)> = self
+            .binaries()
+            .into_iter()
+            .map(|binary| (binary, self.render_restore(binary, &all_rules)))
+            .collect();
+
+        for (index, (binary, payload)) in payloads.iter().enumerate() {
+            if let Err(e) = self.apply_restore(binary, payload).await {
+                // 后面的地址族提交失败时，把已提交的地址族恢复为原规则，两个地址族保持一致
+                let old_rules: Vec<FirewallRule> = self.rules.values().cloned().collect();
+                for (applied, _) in &payloads[..index] {
+                    let previous = self.render_restore(applied, &old_rules);
+                    if let Err(rollback_err) = self.apply_restore(applied, &previous).await {
+                        error!("{}规则回滚失败: {}", applied, rollback_err);
+                    }
+                }
+                return Err(e);
+            }
+        }
+        self.rules = rules;
+        Ok(())
     }
 }
 
@@ -759,7 +1146,19 @@ impl FirewallManager for IptablesManager {
         }
 
         // 创建链
-        self.create_chains().await?;
+        self.create_chains("iptables").await?;
+
+        // ip6tables可选：不可用或内核不支持IPv6 nat时只处理IPv4规则
+        self.ipv6_enabled = match self.create_chains("ip6tables").await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("ip6tables不可用，IPv6目标不会生成内核态规则: {}", e);
+                false
+            }
+        };
+
+        // 用空链内容替换上次运行残留的规则，同时确认iptables-restore可用
+        self.commit(HashMap::new()).await?;
 
         info!("iptables管理器初始化完成，已设置高优先级规则");
         Ok(())
@@ -771,39 +1170,24 @@ impl FirewallManager for IptablesManager {
             rule.listen_port, rule.target_addr
         );
 
-        // 添加DNAT规则
-        if rule.forward_type == ForwardType::DNAT {
-            let dnat_strings = self.generate_dnat_args(rule);
-            let dnat_args: Vec<&str> = dnat_strings.iter().map(|s| s.as_str()).collect();
-            self.execute_iptables(&dnat_args).await?;
-            debug!(
-                "添加DNAT规则: {}:{} -> {}",
-                rule.protocol, rule.listen_port, rule.target_addr
-            );
-        }
-
-        // 添加SNAT规则（masquerade）
-        if rule.forward_type == ForwardType::SNAT {
-            let snat_strings = self.generate_snat_args();
-            let snat_args: Vec<&str> = snat_strings.iter().map(|s| s.as_str()).collect();
-            self.execute_iptables(&snat_args).await?;
-            debug!("添加SNAT规则（masquerade）");
-        }
-
-        // 保存规则到内存
-        self.rules.insert(rule.rule_id.clone(), rule.clone());
+        let mut rules = self.rules.clone();
+        rules.insert(rule.rule_id.clone(), rule.clone());
+        self.commit(rules).await?;
+        debug!(
+            "添加{:?}规则: {}:{} -> {}",
+            rule.forward_type, rule.protocol, rule.listen_port, rule.target_addr
+        );
 
         Ok(())
     }
 
     async fn remove_forward_rule(&mut self, rule_id: &str) -> Result<()> {
-        if let Some(_rule) = self.rules.get(rule_id) {
+        if self.rules.contains_key(rule_id) {
             debug!("删除iptables转发规则: {}", rule_id);
 
-            // iptables规则删除比较复杂，采用重建策略
-            self.rules.remove(rule_id);
-            let remaining_rules: Vec<FirewallRule> = self.rules.values().cloned().collect();
-            self.rebuild_all_rules(&remaining_rules).await?;
+            let mut rules = self.rules.clone();
+            rules.remove(rule_id);
+            self.commit(rules).await?;
         }
 
         Ok(())
@@ -815,12 +1199,10 @@ impl FirewallManager for IptablesManager {
             rule.listen_port, rule.target_addr
         );
 
-        // 更新内存中的规则
-        self.rules.insert(rule.rule_id.clone(), rule.clone());
-
-        // 重建所有规则以确保一致性
-        let all_rules: Vec<FirewallRule> = self.rules.values().cloned().collect();
-        self.rebuild_all_rules(&all_rules).await?;
+        // 整链原子替换，切换目标时不存在没有DNAT规则的窗口
+        let mut rules = self.rules.clone();
+        rules.insert(rule.rule_id.clone(), rule.clone());
+        self.commit(rules).await?;
 
         Ok(())
     }
@@ -828,43 +1210,52 @@ impl FirewallManager for IptablesManager {
     async fn clear_all_rules(&mut self) -> Result<()> {
         info!("清理所有iptables规则");
 
-        // 清空自定义链
-        let _ = self
-            .execute_iptables(&["-t", "nat", "-F", &self.chain_prerouting])
-            .await;
-        let _ = self
-            .execute_iptables(&["-t", "nat", "-F", &self.chain_postrouting])
-            .await;
-
-        // 从主链中移除跳转规则
-        let _ = self
-            .execute_iptables(&[
-                "-t",
-                "nat",
-                "-D",
-                "PREROUTING",
-                "-j",
-                &self.chain_prerouting,
-            ])
-            .await;
-        let _ = self
-            .execute_iptables(&[
-                "-t",
-                "nat",
-                "-D",
-                "POSTROUTING",
-                "-j",
-                &self.chain_postrouting,
-            ])
-            .await;
-
-        // 删除自定义链
-        let _ = self
-            .execute_iptables(&["-t", "nat", "-X", &self.chain_prerouting])
-            .await;
-        let _ = self
-            .execute_iptables(&["-t", "nat", "-X", &self.chain_postrouting])
-            .await;
+        // IPv4和IPv6都清理，ip6tables不可用时命令失败直接忽略
+        for binary in ["iptables", "ip6tables"] {
+            // 清空自定义链
+            let _ = self
+                .execute_iptables(binary, &["-t", "nat", "-F", &self.chain_prerouting])
+                .await;
+            let _ = self
+                .execute_iptables(binary, &["-t", "nat", "-F", &self.chain_postrouting])
+                .await;
+
+            // 从主链中移除跳转规则
+            let _ = self
+                .execute_iptables(
+                    binary,
+                    &[
+                        "-t",
+                        "nat",
+                        "-D",
+                        "PREROUTING",
+                        "-j",
+                        &self.chain_prerouting,
+                    ],
+                )
+                .await;
+            let _ = self
+                .execute_iptables(
+                    binary,
+                    &[
+                        "-t",
+                        "nat",
+                        "-D",
+                        "POSTROUTING",
+                        "-j",
+                        &self.chain_postrouting,
+                    ],
+                )
+                .await;
+
+            // 删除自定义链
+            let _ = self
+                .execute_iptables(binary, &["-t", "nat", "-X", &self.chain_prerouting])
+                .await;
+            let _ = self
+                .execute_iptables(binary, &["-t", "nat", "-X", &self.chain_postrouting])
+                .await;
+        }
 
         // 清空内存中的规则
         self.rules.clear();
@@ -884,34 +1275,19 @@ impl FirewallManager for IptablesManager {
     async fn rebuild_all_rules(&mut self, rules: &[FirewallRule]) -> Result<()> {
         debug!("重建所有iptables规则，共{}条", rules.len());
 
-        // 清空现有规则（保留链结构）
-        let _ = self
-            .execute_iptables(&["-t", "nat", "-F", &self.chain_prerouting])
-            .await;
-        let _ = self
-            .execute_iptables(&["-t", "nat", "-F", &self.chain_postrouting])
-            .await;
-
-        // 重新添加所有规则
-        for rule in rules {
-            if rule.enabled {
-                // 直接添加规则，不更新内存（避免递归调用）
-                if rule.forward_type == ForwardType::DNAT {
-                    let dnat_strings = self.generate_dnat_args(rule);
-                    let dnat_args: Vec<&str> = dnat_strings.iter().map(|s| s.as_str()).collect();
-                    let _ = self.execute_iptables(&dnat_args).await;
-                }
-                if rule.forward_type == ForwardType::SNAT {
-                    let snat_strings = self.generate_snat_args();
-                    let snat_args: Vec<&str> = snat_strings.iter().map(|s| s.as_str()).collect();
-                    let _ = self.execute_iptables(&snat_args).await;
-                }
-            }
-        }
+        let rules = rules
+            .iter()
+            .map(|rule| (rule.rule_id.clone(), rule.clone()))
+            .collect();
+        self.commit(rules).await?;
 
         debug!("iptables规则重建完成");
         Ok(())
     }
+
+    fn set_listen_addrs(&mut self, listen_addrs: Vec<String>) {
+        self.listen_addrs = listen_addrs;
+    }
 }
 
 // ================================
@@ -931,10 +1307,16 @@ impl FirewallScheduler {
         config: Config,
         common_manager: CommonManager,
     ) -> Result<Self> {
-        let listen_addr = config.network.first();
+        let listen_addrs = config.network.ips();
         let manager: Box<dyn FirewallManager> = match backend {
-            FirewallBackend::Nftables => Box::new(NftablesManager::new(listen_addr)),
-            FirewallBackend::Iptables => Box::new(IptablesManager::new(listen_addr)),
+            FirewallBackend::Nftables => Box::new(NftablesManager::new(listen_addrs)),
+            #[cfg(target_os = "linux")]
+            FirewallBackend::NftablesNetlink => Box::new(NftablesNetlinkManager::new(listen_addrs)),
+            #[cfg(not(target_os = "linux"))]
+            FirewallBackend::NftablesNetlink => {
+                return Err(anyhow::anyhow!("nftables-netlink防火墙后端只在Linux上支持"));
+            }
+            FirewallBackend::Iptables => Box::new(IptablesManager::new(listen_addrs)),
             #[cfg(target_os = "macos")]
             FirewallBackend::Pfctl => Box::new(PfctlManager::new()),
             #[cfg(not(target_os = "macos"))]
@@ -967,27 +1349,53 @@ impl FirewallScheduler {
     async fn create_initial_rules(&mut self) -> Result<()> {
         info!("创建初始防火墙规则");
 
-        for (index, rule_config) in self.config.rules.iter().enumerate() {
-            // 获取最佳目标
-            if let Ok(best_target) = self.common_manager.get_best_target(&rule_config.name).await {
-                let target_addr = best_target.to_string();
+        // 所有规则一次提交，启动时只重建一次规则表
+        let mut rules = HashMap::new();
+        let rule_configs = self.config.rules.clone();
+        for (index, rule_config) in rule_configs.iter().enumerate() {
+            self.build_rules_for(index, rule_config, &mut rules).await;
+        }
 
-                // 为每个协议创建规则
-                let protocols = rule_config.get_protocols();
-                for protocol in protocols {
-                    let rule_id = format!("{}_{}", rule_config.name, protocol);
+        self.commit_rules(rules).await
+    }
 
-                    // 创建DNAT规则
-                    let dnat_rule = FirewallRule::new(
-                        format!("{}_dnat", rule_id),
-                        rule_config.listen_port,
-                        protocol.clone(),
-                        target_addr.clone(),
-                        ForwardType::DNAT,
-                        index,
-                    );
+    // 为单个转发规则的每个协议生成DNAT/SNAT规则
+    async fn build_rules_for(
+        &self,
+        index: usize,
+        rule_config: &ForwardRule,
+        rules: &mut HashMap<String, FirewallRule>,
+    ) {
+        // 内核态DNAT只能指向单个目标，负载均衡策略按优先级故障转移处理
+        if rule_config.get_strategy() != LoadBalanceStrategy::Priority {
+            warn!(
+                "⚠️  规则 {} 的负载均衡策略在内核态转发中不生效，按优先级故障转移处理",
+                rule_config.name
+            );
+        }
+
+        // 获取最佳目标
+        if let Ok(best_target) = self.common_manager.get_best_target(&rule_config.name).await {
+            let target_addr = best_target.to_string();
+
+            // 为每个协议创建规则
+            let protocols = rule_config.get_protocols();
+            for protocol in protocols {
+                let rule_id = format!("{}_{}", rule_config.name, protocol);
+
+                // 创建DNAT规则
+                let dnat_rule = FirewallRule::new(
+                    format!("{}_dnat", rule_id),
+                    rule_config.listen_port,
+                    protocol.clone(),
+                    target_addr.clone(),
+                    ForwardType::DNAT,
+                    index,
+                );
+                rules.insert(dnat_rule.rule_id.clone(), dnat_rule);
 
-                    // 创建SNAT规则
+                // 创建SNAT规则，目标经网关回程时可按规则关闭
+                if rule_config.get_masquerade() {
                     let snat_rule = FirewallRule::new(
                         format!("{}_snat", rule_id),
                         rule_config.listen_port,
@@ -996,101 +1404,158 @@ impl FirewallScheduler {
                         ForwardType::SNAT,
                         index,
                     );
-
-                    // 添加规则
-                    self.manager.add_forward_rule(&dnat_rule).await?;
-                    self.manager.add_forward_rule(&snat_rule).await?;
-
-                    // 保存到内存
-                    let mut rules = self.rules.write().await;
-                    rules.insert(dnat_rule.rule_id.clone(), dnat_rule);
                     rules.insert(snat_rule.rule_id.clone(), snat_rule);
-
-                    info!(
-                        "创建规则: {} {} -> {}",
-                        rule_config.name, protocol, target_addr
-                    );
                 }
-            } else {
-                warn!("规则 {} 没有可用的目标地址", rule_config.name);
+
+                info!(
+                    "创建规则: {} {} -> {}",
+                    rule_config.name, protocol, target_addr
+                );
             }
+        } else {
+            warn!("规则 {} 没有可用的目标地址", rule_config.name);
         }
+    }
 
-        Ok(())
+    // 删除单个转发规则对应的所有协议的DNAT/SNAT规则
+    fn remove_rules_for(rule_config: &ForwardRule, rules: &mut HashMap<String, FirewallRule>) {
+        for protocol in rule_config.get_protocols() {
+            for suffix in ["dnat", "snat"] {
+                rules.remove(&format!("{}_{}_{}", rule_config.name, protocol, suffix));
+            }
+        }
+
+        info!("删除规则: {}", rule_config.name);
     }
 
-    pub async fn sync_with_targets(&mut self) -> Result<()> {
-        debug!("同步防火墙规则与健康检查结果");
+    // 将完整规则集作为一个事务提交，成功后才更新调度器记录的规则
+    async fn commit_rules(&mut self, rules: HashMap<String, FirewallRule>) -> Result<()> {
+        let list: Vec<FirewallRule> = rules.values().cloned().collect();
+        self.manager.rebuild_all_rules(&list).await?;
+        *self.rules.write().await = rules;
+        Ok(())
+    }
 
-        // 收集需要更新的规则
-        let mut rules_to_update = Vec::new();
+    // 按健康检查结果把规则的目标改为当前最佳目标，返回是否有变化
+    async fn retarget_rules(&self, rules: &mut HashMap<String, FirewallRule>) -> bool {
+        let mut changed = false;
 
         for rule_config in &self.config.rules {
             if let Ok(best_target) = self.common_manager.get_best_target(&rule_config.name).await {
                 let target_addr = best_target.to_string();
 
-                // 检查是否需要更新规则
-                let protocols = rule_config.get_protocols();
-                for protocol in protocols {
+                for protocol in rule_config.get_protocols() {
                     let dnat_rule_id = format!("{}_{}_dnat", rule_config.name, protocol);
                     let snat_rule_id = format!("{}_{}_snat", rule_config.name, protocol);
 
-                    let existing_dnat_rule = {
-                        let rules = self.rules.read().await;
-                        rules.get(&dnat_rule_id).cloned()
+                    let Some(dnat_rule) = rules.get_mut(&dnat_rule_id) else {
+                        continue;
                     };
+                    if dnat_rule.target_addr == target_addr {
+                        continue;
+                    }
+                    info!(
+                        "🔄 内核态转发规则更新: {} {} {} -> {}",
+                        rule_config.name, protocol, dnat_rule.target_addr, target_addr
+                    );
+                    dnat_rule.target_addr = target_addr.clone();
 
-                    if let Some(existing_rule) = existing_dnat_rule {
-                        if existing_rule.target_addr != target_addr {
-                            // 需要更新规则
-                            info!(
-                                "🔄 内核态转发规则更新: {} {} {} -> {}",
-                                rule_config.name, protocol, existing_rule.target_addr, target_addr
-                            );
-
-                            // 创建更新后的DNAT和SNAT规则
-                            let mut updated_dnat = existing_rule.clone();
-                            updated_dnat.target_addr = target_addr.clone();
-
-                            let existing_snat_rule = {
-                                let rules = self.rules.read().await;
-                                rules.get(&snat_rule_id).cloned()
-                            };
-
-                            if let Some(mut updated_snat) = existing_snat_rule {
-                                updated_snat.target_addr = target_addr.clone();
-                                rules_to_update.push((updated_dnat, updated_snat));
-                            }
-                        }
+                    // DNAT和SNAT同时更新（关闭masquerade的规则没有SNAT）
+                    if let Some(snat_rule) = rules.get_mut(&snat_rule_id) {
+                        snat_rule.target_addr = target_addr.clone();
                     }
+                    changed = true;
                 }
             }
         }
 
-        // 执行规则更新
-        for (dnat_rule, snat_rule) in rules_to_update {
-            // 更新DNAT规则
-            if let Err(e) = self.manager.update_forward_rule(&dnat_rule).await {
-                error!("更新DNAT规则失败: {} - {}", dnat_rule.rule_id, e);
-                continue;
+        changed
+    }
+
+    // 热重载：在当前规则集上删除/创建变化的规则并同步目标，整体一次提交
+    pub async fn apply_config(&mut self, new_config: Config, diff: &RuleDiff) -> Result<()> {
+        // 监听地址变化时所有规则都在diff.restarted中，按新地址重建DNAT匹配
+        let network_changed = self.config.network != new_config.network;
+        if network_changed {
+            info!("监听地址变化，按新地址重建内核态转发规则");
+            self.manager.set_listen_addrs(new_config.network.ips());
+        }
+
+        let mut rules = self.rules.read().await.clone();
+        for rule_config in diff.removed.iter() {
+            Self::remove_rules_for(rule_config, &mut rules);
+        }
+        // masquerade开关变化时需要重建该规则的SNAT，与监听变化一样按重建处理
+        let masquerade_changed: Vec<&ForwardRule> = diff
+            .updated
+            .iter()
+            .filter(|rule_config| {
+                self.config.rules.iter().any(|old| {
+                    old.name == rule_config.name
+                        && old.get_masquerade() != rule_config.get_masquerade()
+                })
+            })
+            .collect();
+        for rule_config in diff
+            .restarted
+            .iter()
+            .chain(masquerade_changed.iter().copied())
+        {
+            // 旧规则的协议可能与新规则不同，按旧配置删除
+            if let Some(old_rule) = self
+                .config
+                .rules
+                .iter()
+                .find(|r| r.name == rule_config.name)
+            {
+                Self::remove_rules_for(old_rule, &mut rules);
             }
+        }
 
-            // 更新SNAT规则
-            if let Err(e) = self.manager.update_forward_rule(&snat_rule).await {
-                error!("更新SNAT规则失败: {} - {}", snat_rule.rule_id, e);
-                continue;
+        let old_config = std::mem::replace(&mut self.config, new_config);
+
+        let rule_configs = self.config.rules.clone();
+        for (index, rule_config) in rule_configs.iter().enumerate() {
+            let needs_create = diff
+                .added
+                .iter()
+                .chain(diff.restarted.iter())
+                .chain(masquerade_changed.iter().copied())
+                .any(|r| r.name == rule_config.name);
+            if needs_create {
+                self.build_rules_for(index, rule_config, &mut rules).await;
             }
+        }
+        self.retarget_rules(&mut rules).await;
 
-            // 更新内存中的规则
-            {
-                let mut rules = self.rules.write().await;
-                rules.insert(dnat_rule.rule_id.clone(), dnat_rule);
-                rules.insert(snat_rule.rule_id.clone(), snat_rule);
+        if !network_changed && *self.rules.read().await == rules {
+            return Ok(());
+        }
+        if let Err(e) = self.commit_rules(rules).await {
+            // 提交失败时内核规则保持不变，配置和监听地址也回退以便下次重载重新计算差异
+            if network_changed {
+                self.manager.set_listen_addrs(old_config.network.ips());
             }
+            self.config = old_config;
+            return Err(e);
+        }
+        Ok(())
+    }
 
-            debug!("✅ 内核态转发规则更新完成");
+    pub async fn sync_with_targets(&mut self) -> Result<()> {
+        debug!("同步防火墙规则与健康检查结果");
+
+        let mut rules = self.rules.read().await.clone();
+        if !self.retarget_rules(&mut rules).await {
+            return Ok(());
         }
 
+        // 所有目标变化的DNAT/SNAT规则一次提交，不会出现只更新了一半的规则
+        if let Err(e) = self.commit_rules(rules).await {
+            error!("更新内核态转发规则失败: {e}");
+            return Err(e);
+        }
+        debug!("✅ 内核态转发规则更新完成");
         Ok(())
     }
 
@@ -1136,8 +1601,9 @@ pub fn detect_firewall_backend() -> FirewallBackend {
         return FirewallBackend::Iptables;
     }
 
-    warn!("未检测到支持的防火墙后端，默认使用nftables");
-    FirewallBackend::Nftables
+    // 没有nft/iptables命令的精简系统 (如最小容器) 直接通过netlink操作nf_tables
+    warn!("未检测到nft/iptables命令，使用nftables netlink后端");
+    FirewallBackend::NftablesNetlink
 }
 
 #[cfg(test)]
@@ -1170,4 +1636,320 @@ mod tests {
         assert_eq!(NftablesManager::detect_ip_version("::1"), "ip6");
         assert_eq!(NftablesManager::detect_ip_version("::"), "ip6");
     }
+
+    #[test]
+    fn test_dnat_per_listen_addr() {
+        let listen_addrs = vec![
+            "192.168.1.1".to_string(),
+            "10.0.0.1".to_string(),
+            "fd00::1".to_string(),
+        ];
+        let rule = FirewallRule::new(
+            "rdp".to_string(),
+            3389,
+            "tcp".to_string(),
+            "192.168.1.10:3389".to_string(),
+            ForwardType::DNAT,
+            0,
+        );
+
+        // nftables: IPv4目标只为IPv4监听地址生成规则
+        let rules = NftablesManager::new(listen_addrs.clone()).generate_dnat_rules(&rule);
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            rules[0],
+            "ip daddr 192.168.1.1 tcp dport 3389 dnat ip to 192.168.1.10:3389"
+        );
+        assert!(rules[1].contains("ip daddr 10.0.0.1 tcp dport 3389"));
+
+        // iptables: IPv4目标使用iptables，跳过IPv6监听地址
+        let manager = IptablesManager::new(listen_addrs);
+        let (binary, rules) = manager.generate_dnat_args(&rule).unwrap();
+        assert_eq!(binary, "iptables");
+        assert_eq!(rules.len(), 2);
+        assert!(rules[1].join(" ").contains("-d 10.0.0.1 -p tcp"));
+
+        // IPv6目标使用ip6tables，只为IPv6监听地址生成规则
+        let mut ipv6 = rule.clone();
+        ipv6.target_addr = "[fd00::10]:3389".to_string();
+        let (binary, rules) = manager.generate_dnat_args(&ipv6).unwrap();
+        assert_eq!(binary, "ip6tables");
+        assert_eq!(rules.len(), 1);
+        assert!(rules[0]
+            .join(" ")
+            .ends_with("-d fd00::1 -p tcp --dport 3389 -j DNAT --to-destination [fd00::10]:3389"));
+
+        // 通配地址不限制目标地址
+        let rules = NftablesManager::new(vec!["0.0.0.0".to_string()]).generate_dnat_rules(&rule);
+        assert_eq!(rules.len(), 1);
+        assert!(!rules[0].contains("daddr"));
+
+        // 热重载更新监听地址后按新地址生成规则
+        let mut manager = NftablesManager::new(vec!["0.0.0.0".to_string()]);
+        manager.set_listen_addrs(vec!["192.168.1.2".to_string()]);
+        let rules = manager.generate_dnat_rules(&rule);
+        assert_eq!(rules.len(), 1);
+        assert!(rules[0].starts_with("ip daddr 192.168.1.2 tcp dport 3389"));
+    }
+
+    #[test]
+    fn test_nft_ruleset_script() {
+        let manager = NftablesManager::new(vec!["192.168.1.1".to_string()]);
+        let rules = vec![
+            FirewallRule::new(
+                "rdp_tcp_snat".to_string(),
+                3389,
+                "tcp".to_string(),
+                "192.168.1.10:3389".to_string(),
+                ForwardType::SNAT,
+                0,
+            ),
+            FirewallRule::new(
+                "rdp_tcp_dnat".to_string(),
+                3389,
+                "tcp".to_string(),
+                "192.168.1.10:3389".to_string(),
+                ForwardType::DNAT,
+                0,
+            ),
+        ];
+
+        // 删除旧表和建新表在同一个脚本中，由nft作为单个事务提交
+        let script = manager.render_ruleset(&rules);
+        let lines: Vec<&str> = script.lines().collect();
+        assert_eq!(lines[0], "table inet smart_forward");
+        assert_eq!(lines[1], "delete table inet smart_forward");
+        assert!(script.contains("chain prerouting { type nat hook prerouting priority -150; }"));
+        assert!(script.contains(
+            "add rule inet smart_forward prerouting ip daddr 192.168.1.1 tcp dport 3389 dnat ip to 192.168.1.10:3389"
+        ));
+        // SNAT只匹配DNAT后发往目标的连接
+        assert!(script.contains(
+            "add rule inet smart_forward postrouting ct status dnat ip daddr 192.168.1.10 tcp dport 3389 masquerade"
+        ));
+
+        // 规则按配置顺序输出，结果稳定
+        let mut reversed = rules.clone();
+        reversed.reverse();
+        assert_eq!(manager.render_ruleset(&reversed), script);
+
+        // 共用目标的规则只生成一条SNAT
+        let mut shared = rules.clone();
+        let mut other = rules[0].clone();
+        other.rule_id = "rdp2_tcp_snat".to_string();
+        shared.push(other);
+        assert_eq!(
+            manager
+                .render_ruleset(&shared)
+                .matches("masquerade")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_iptables_snat_scope() {
+        let manager = IptablesManager::new(vec!["192.168.1.1".to_string()]);
+        let rule = FirewallRule::new(
+            "dns_udp_snat".to_string(),
+            53,
+            "udp".to_string(),
+            "192.168.1.10".to_string(),
+            ForwardType::SNAT,
+            0,
+        );
+        // 目标未写端口时使用监听端口
+        let (binary, args) = manager.generate_snat_args(&rule).unwrap();
+        assert_eq!(binary, "iptables");
+        assert_eq!(
+            args.join(" "),
+            "-A SMART_FORWARD_POSTROUTING -p udp -d 192.168.1.10 --dport 53 -m conntrack --ctstate DNAT -j MASQUERADE"
+        );
+
+        let mut ipv6 = rule.clone();
+        ipv6.target_addr = "[fd00::10]:53".to_string();
+        let (binary, args) = manager.generate_snat_args(&ipv6).unwrap();
+        assert_eq!(binary, "ip6tables");
+        assert!(args.join(" ").contains("-d fd00::10 --dport 53"));
+    }
+
+    #[test]
+    fn test_iptables_restore_payload() {
+        let mut manager =
+            IptablesManager::new(vec!["192.168.1.1".to_string(), "fd00::1".to_string()]);
+        manager.ipv6_enabled = true;
+        let rules = vec![
+            FirewallRule::new(
+                "rdp_tcp_dnat".to_string(),
+                3389,
+                "tcp".to_string(),
+                "192.168.1.10:3389".to_string(),
+                ForwardType::DNAT,
+                0,
+            ),
+            FirewallRule::new(
+                "rdp_tcp_snat".to_string(),
+                3389,
+                "tcp".to_string(),
+                "192.168.1.10:3389".to_string(),
+                ForwardType::SNAT,
+                0,
+            ),
+            FirewallRule::new(
+                "web_tcp_dnat".to_string(),
+                443,
+                "tcp".to_string(),
+                "[fd00::20]:443".to_string(),
+                ForwardType::DNAT,
+                1,
+            ),
+        ];
+
+        // 只声明两条自定义链，配合 --noflush 原子替换链内容
+        assert_eq!(
+            manager.render_restore("iptables", &rules),
+            "*nat\n\
+             :SMART_FORWARD_PREROUTING - [0:0]\n\
+             :SMART_FORWARD_POSTROUTING - [0:0]\n\
+             -A SMART_FORWARD_PREROUTING -d 192.168.1.1 -p tcp --dport 3389 -j DNAT --to-destination 192.168.1.10:3389\n\
+             -A SMART_FORWARD_POSTROUTING -p tcp -d 192.168.1.10 --dport 3389 -m conntrack --ctstate DNAT -j MASQUERADE\n\
+             COMMIT\n"
+        );
+
+        // IPv6规则只出现在ip6tables的内容中
+        let payload = manager.render_restore("ip6tables", &rules);
+        assert!(payload.contains(
+            "-A SMART_FORWARD_PREROUTING -d fd00::1 -p tcp --dport 443 -j DNAT --to-destination [fd00::20]:443"
+        ));
+        assert!(!payload.contains("192.168.1.10"));
+    }
+
+    // 用临时目录中的替身iptables-restore/ip6tables-restore验证：IPv6提交失败时IPv4恢复为原规则
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_iptables_commit_rollback() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("iptables.log");
+        let scripts = [
+            (
+                "iptables-restore",
+                format!("#!/bin/sh\ncat >> {}\n", log.display()),
+            ),
+            (
+                "ip6tables-restore",
+                "#!/bin/sh\ncat >/dev/null\nexit 1\n".to_string(),
+            ),
+        ];
+        for (name, script) in scripts {
+            let path = dir.path().join(name);
+            std::fs::write(&path, script).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+
+        let rule = |id: &str, target: &str| {
+            FirewallRule::new(
+                id.to_string(),
+                80,
+                "tcp".to_string(),
+                target.to_string(),
+                ForwardType::DNAT,
+                0,
+            )
+        };
+        let mut manager = IptablesManager::new(vec!["0.0.0.0".to_string()]);
+        manager.ipv6_enabled = true;
+        manager.restore_prefix = format!("{}/", dir.path().display());
+        manager
+            .rules
+            .insert("old".to_string(), rule("old", "192.168.1.10:80"));
+
+        let new_rules = HashMap::from([
+            ("v4".to_string(), rule("v4", "192.168.1.20:80")),
+            ("v6".to_string(), rule("v6", "[fd00::20]:80")),
+        ]);
+        assert!(manager.commit(new_rules).await.is_err());
+
+        // IPv4先提交新规则，IPv6失败后再次提交原规则
+        let applied = std::fs::read_to_string(&log).unwrap();
+        let new_at = applied.find("192.168.1.20:80").unwrap();
+        let old_at = applied.find("192.168.1.10:80").unwrap();
+        assert!(new_at < old_at);
+        assert!(manager.rules.contains_key("old") && manager.rules.len() == 1);
+    }
+
+    // 在独立的网络命名空间中提交到真实内核，不影响宿主机规则
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "需要root或CAP_SYS_ADMIN创建网络命名空间，使用 cargo test -- --ignored 运行"]
+    fn test_netlink_backend_in_netns() {
+        std::thread::spawn(|| {
+            // CLONE_NEWNET只作用于当前线程
+            // SAFETY: unshare() 只接收标志位参数，只影响调用线程的网络命名空间
+            if unsafe { libc::unshare(libc::CLONE_NEWNET) } != 0 {
+                panic!("无法创建网络命名空间: {}", std::io::Error::last_os_error());
+            }
+
+            let manager =
+                NftablesNetlinkManager::new(vec!["192.168.1.1".to_string(), "fd00::1".to_string()]);
+            let rule = |id: &str, protocol: &str, target: &str, forward_type| {
+                FirewallRule::new(
+                    id.to_string(),
+                    3389,
+                    protocol.to_string(),
+                    target.to_string(),
+                    forward_type,
+                    0,
+                )
+            };
+            let mut rules = vec![
+                rule(
+                    "rdp_tcp_dnat",
+                    "tcp",
+                    "192.168.1.10:3389",
+                    ForwardType::DNAT,
+                ),
+                rule(
+                    "rdp_udp_dnat",
+                    "udp",
+                    "192.168.1.10:3389",
+                    ForwardType::DNAT,
+                ),
+                rule(
+                    "rdp_tcp_snat",
+                    "tcp",
+                    "192.168.1.10:3389",
+                    ForwardType::SNAT,
+                ),
+                rule("v6_tcp_dnat", "tcp", "[fd00::10]:3389", ForwardType::DNAT),
+                rule("v6_tcp_snat", "tcp", "[fd00::10]:3389", ForwardType::SNAT),
+            ];
+
+            nft_netlink::send_batch(manager.build_batch(&rules)).unwrap();
+            assert_eq!(nft_netlink::count_rules("smart_forward").unwrap(), 5);
+
+            // 切换目标：整表在一个事务中替换
+            rules[0].target_addr = "192.168.1.11:3389".to_string();
+            nft_netlink::send_batch(manager.build_batch(&rules)).unwrap();
+            assert_eq!(nft_netlink::count_rules("smart_forward").unwrap(), 5);
+
+            // 批次中有无效消息时整批回滚，原规则保持不变
+            let mut batch = manager.build_batch(&rules[..1]);
+            batch.add_rule("smart_forward", "missing", &nft_netlink::masquerade());
+            assert!(nft_netlink::send_batch(batch).is_err());
+            assert_eq!(nft_netlink::count_rules("smart_forward").unwrap(), 5);
+
+            // 清理：表不存在时重复删除也不会失败
+            for _ in 0..2 {
+                let mut batch = Batch::new();
+                batch.add_table("smart_forward");
+                batch.del_table("smart_forward");
+                nft_netlink::send_batch(batch).unwrap();
+            }
+            assert_eq!(nft_netlink::count_rules("smart_forward").unwrap(), 0);
+        })
+        .join()
+        .unwrap();
+    }
 }
@@ -1,5 +1,5 @@
 // 智能网络转发器 - 完整转发器实现
-use crate::common::CommonManager;
+use crate::common::{CommonManager, ConnectionGuard};
 use crate::config::{Config, ForwardRule};
 use crate::firewall::FirewallScheduler;
 use crate::utils::{get_standard_stats, get_stats_with_target, ConnectionStats};
@@ -7,11 +7,14 @@ use anyhow::Result;
 use async_trait::async_trait;
 use log::{error, info, warn};
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
 use tokio::sync::{Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
 // ================================
 // 转发器特征定义
@@ -20,9 +23,13 @@ use tokio::sync::{Mutex, RwLock};
 pub trait Forwarder: Send + Sync {
     async fn start(&mut self) -> Result<()>;
     async fn stop(&mut self);
-    fn is_running(&self) -> bool;
     #[allow(dead_code)]
-    fn get_stats(&self) -> HashMap<String, String>;
+    fn is_running(&self) -> bool;
+    async fn get_stats(&self) -> HashMap<String, String>;
+    // 正在转发的TCP连接数（优雅关闭时等待其结束）
+    fn active_connections(&self) -> usize {
+        0
+    }
     #[allow(dead_code)]
     fn as_any(&self) -> &dyn std::any::Any;
     fn as_any_mut(&mut self) -> &mut dyn std::any::Any;
@@ -31,13 +38,35 @@ pub trait Forwarder: Send + Sync {
 // ================================
 // TCP 转发器
 // ================================
+
+// 单向转发结果
+#[derive(Default)]
+struct ForwardOutcome {
+    bytes: u64,         // 转发的字节数
+    reader_reset: bool, // 读取端是否被重置（RST）
+}
+
+// 单次连接目标的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+// 未启动的转发器使用已取消的令牌，is_running返回false
+fn stopped_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    token.cancel();
+    token
+}
+
 pub struct TCPForwarder {
     listen_addr: String,
     name: String,
     buffer_size: usize,
     target_addr: Arc<RwLock<String>>,
     stats: Arc<RwLock<ConnectionStats>>,
-    running: Arc<RwLock<bool>>,
+    shutdown: CancellationToken, // 停止时取消，监听任务退出并关闭端口
+    accept_task: Option<JoinHandle<()>>,
+    common_manager: Option<CommonManager>,
+    rule_name: String,
+    active_connections: Arc<AtomicUsize>,
 }
 
 impl TCPForwarder {
@@ -48,13 +77,27 @@ impl TCPForwarder {
             buffer_size,
             target_addr: Arc::new(RwLock::new(String::new())),
             stats: Arc::new(RwLock::new(ConnectionStats::default())),
-            running: Arc::new(RwLock::new(false)),
+            shutdown: stopped_token(),
+            accept_task: None,
+            common_manager: None,
+            rule_name: name.to_string(),
+            active_connections: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    // 设置公共管理器后，每个连接按规则的负载均衡策略选择目标
+    pub fn set_common_manager(&mut self, common_manager: CommonManager, rule_name: &str) {
+        self.common_manager = Some(common_manager);
+        self.rule_name = rule_name.to_string();
+    }
+
+    // 同一规则多个监听地址的转发器共用统计
+    pub fn share_stats(&mut self, stats: Arc<RwLock<ConnectionStats>>) {
+        self.stats = stats;
+    }
+
     pub async fn start_with_target(&mut self, target: &str) -> Result<()> {
         *self.target_addr.write().await = target.to_string();
-        *self.running.write().await = true;
 
         let listener = match TcpListener::bind(&self.listen_addr).await {
             Ok(listener) => {
@@ -70,33 +113,63 @@ impl TCPForwarder {
                 ));
             }
         };
+        self.shutdown = CancellationToken::new();
+        let shutdown = self.shutdown.clone();
         let target_addr = self.target_addr.clone();
         let stats = self.stats.clone();
-        let running = self.running.clone();
         let name = self.name.clone();
         let buffer_size = self.buffer_size;
+        let common_manager = self.common_manager.clone();
+        let lb_rule_name = self.rule_name.clone();
+        let active_connections = self.active_connections.clone();
 
-        tokio::spawn(async move {
-            while *running.read().await {
-                match listener.accept().await {
-                    Ok((stream, _)) => {
-                        let target_str = target_addr.read().await.clone();
+        // 已建立的连接不随监听任务取消，继续转发直到结束（热重载和优雅关闭不中断现有连接）
+        self.accept_task = Some(tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    _ = shutdown.cancelled() => break,
+                    accepted = listener.accept() => accepted,
+                };
+                match accepted {
+                    Ok((stream, client_addr)) => {
+                        let fallback_target = target_addr.read().await.clone();
                         let stats = stats.clone();
                         let rule_name = name.clone();
+                        let common_manager = common_manager.clone();
+                        let lb_rule_name = lb_rule_name.clone();
+                        let active_connections = active_connections.clone();
+                        active_connections.fetch_add(1, Ordering::Relaxed);
 
                         tokio::spawn(async move {
-                            if (Self::handle_connection(
+                            // 按负载均衡策略为本连接选择目标，失败时使用转发器当前目标
+                            let (target_str, connection_guard) = match &common_manager {
+                                Some(manager) => match manager
+                                    .select_target(&lb_rule_name, Some(client_addr))
+                                    .await
+                                {
+                                    Ok(addr) => {
+                                        (addr.to_string(), Some(manager.track_connection(addr)))
+                                    }
+                                    Err(_) => (fallback_target, None),
+                                },
+                                None => (fallback_target, None),
+                            };
+
+                            if let Err(e) = Self::handle_connection(
                                 stream,
                                 &target_str,
                                 buffer_size,
                                 stats,
-                                &rule_name,
+                                common_manager.as_ref(),
+                                &lb_rule_name,
+                                connection_guard,
                             )
-                            .await)
-                                .is_err()
+                            .await
                             {
-                                // 连接处理失败，但不记录详细错误
+                                // 连接处理失败，只在调试时记录
+                                log::debug!("TCP转发器 {rule_name} 连接处理失败: {e}");
                             }
+                            active_connections.fetch_sub(1, Ordering::Relaxed);
                         });
                     }
                     Err(e) => {
@@ -108,7 +181,7 @@ impl TCPForwarder {
                     }
                 }
             }
-        });
+        }));
 
         Ok(())
     }
@@ -126,10 +199,12 @@ impl TCPForwarder {
         target_addr: &str,
         buffer_size: usize,
         stats: Arc<RwLock<ConnectionStats>>,
-        _rule_name: &str,
+        common_manager: Option<&CommonManager>,
+        rule_name: &str,
+        mut _connection_guard: Option<ConnectionGuard>, // 重试时替换为新目标的连接计数
     ) -> Result<()> {
         // 解析已解析的目标地址字符串（来自CommonManager的DNS解析结果）
-        let target: std::net::SocketAddr = target_addr
+        let mut target: std::net::SocketAddr = target_addr
             .parse()
             .map_err(|e| anyhow::anyhow!("TCP目标地址解析失败: {} - {}", target_addr, e))?;
 
@@ -138,16 +213,53 @@ impl TCPForwarder {
         // 优化TCP：降低延迟
         let _ = client_stream.set_nodelay(true);
 
-        // 直接连接，不重试（让健康检查快速切换到正确地址）
-        let mut target_stream = match tokio::time::timeout(
-            tokio::time::Duration::from_secs(3), // 缩短连接超时时间
-            tokio::net::TcpStream::connect(target),
-        )
-        .await
-        {
-            Ok(Ok(stream)) => stream,
-            Ok(Err(e)) => return Err(anyhow::anyhow!("连接目标失败: {}", e)),
-            Err(_) => return Err(anyhow::anyhow!("连接目标超时")),
+        // 连接失败上报给公共管理器做被动健康检测；配置了connect_retries时
+        // 按规则目标顺序重试其他健康目标，所有尝试共享同一个总超时
+        let (retries, deadline) = match common_manager {
+            Some(manager) => manager.connect_policy(rule_name).await,
+            None => (0, CONNECT_TIMEOUT),
+        };
+        let deadline = Instant::now() + deadline;
+        let mut tried = Vec::new();
+
+        let mut target_stream = loop {
+            let attempt_timeout =
+                CONNECT_TIMEOUT.min(deadline.saturating_duration_since(Instant::now()));
+            let connect_result =
+                match tokio::time::timeout(attempt_timeout, TcpStream::connect(target)).await {
+                    Ok(Ok(stream)) => Ok(stream),
+                    Ok(Err(e)) => Err(format!("连接目标失败: {e}")),
+                    Err(_) => Err("连接目标超时".to_string()),
+                };
+
+            let reason = match connect_result {
+                Ok(stream) => {
+                    if let Some(manager) = common_manager {
+                        manager.report_connect_success(rule_name, target);
+                    }
+                    break stream;
+                }
+                Err(reason) => reason,
+            };
+
+            let Some(manager) = common_manager else {
+                return Err(anyhow::anyhow!(reason));
+            };
+            manager
+                .report_connect_failure(rule_name, target, &reason)
+                .await;
+
+            tried.push(target);
+            if tried.len() > retries as usize || Instant::now() >= deadline {
+                return Err(anyhow::anyhow!("{} ({} 次尝试)", reason, tried.len()));
+            }
+            let Some(next) = manager.next_connect_target(rule_name, &tried).await else {
+                return Err(anyhow::anyhow!("{} (无其他健康目标可重试)", reason));
+            };
+
+            log::debug!("规则 {rule_name} 连接 {target} 失败: {reason}，重试目标 {next}");
+            target = next;
+            _connection_guard = Some(manager.track_connection(next));
         };
 
         // 目标侧同样禁用Nagle算法
@@ -159,7 +271,7 @@ impl TCPForwarder {
         let mut client_buffer = vec![0u8; buffer_size];
         let mut target_buffer = vec![0u8; buffer_size];
 
-        let (client_to_target, target_to_client) = tokio::join!(
+        let (_client_to_target, target_to_client) = tokio::join!(
             Self::forward_data(
                 &mut client_read,
                 &mut target_write,
@@ -176,12 +288,13 @@ impl TCPForwarder {
             ),
         );
 
-        // 简化错误处理，连接断开是正常现象，减少日志噪音
-        if client_to_target.is_err() {
-            // 连接断开不记录错误日志
-        }
-        if target_to_client.is_err() {
-            // 连接断开不记录错误日志
+        // 连接断开是正常现象，不记录日志；目标未返回任何数据就重置连接视为连接失败
+        if target_to_client.reader_reset && target_to_client.bytes == 0 {
+            if let Some(manager) = common_manager {
+                manager
+                    .report_connect_failure(rule_name, target, "连接被目标重置")
+                    .await;
+            }
         }
 
         Ok(())
@@ -193,37 +306,48 @@ impl TCPForwarder {
         buffer: &mut [u8],
         stats: &Arc<RwLock<ConnectionStats>>,
         is_sent: bool,
-    ) -> Result<()>
+    ) -> ForwardOutcome
     where
         R: tokio::io::AsyncRead + Unpin,
         W: tokio::io::AsyncWrite + Unpin,
     {
-        let mut total_bytes = 0u64;
+        let mut outcome = ForwardOutcome::default();
         loop {
-            let n = reader.read(buffer).await?;
-            if n == 0 {
+            let n = match reader.read(buffer).await {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) => {
+                    outcome.reader_reset = e.kind() == std::io::ErrorKind::ConnectionReset;
+                    break;
+                }
+            };
+
+            if writer.write_all(&buffer[..n]).await.is_err() {
                 break;
             }
-
-            writer.write_all(&buffer[..n]).await?;
-            total_bytes += n as u64;
+            outcome.bytes += n as u64;
         }
 
         // 批量更新统计信息，减少锁竞争
-        if total_bytes > 0 {
+        if outcome.bytes > 0 {
             if is_sent {
-                stats.write().await.add_bytes_sent(total_bytes);
+                stats.write().await.add_bytes_sent(outcome.bytes);
             } else {
-                stats.write().await.add_bytes_received(total_bytes);
+                stats.write().await.add_bytes_received(outcome.bytes);
             }
         }
 
-        Ok(())
+        outcome
     }
 
-    pub fn get_stats(&self) -> HashMap<String, String> {
-        let stats = self.stats.blocking_read();
-        get_standard_stats(&stats)
+    pub async fn get_stats(&self) -> HashMap<String, String> {
+        let stats = self.stats.read().await;
+        let mut result = get_standard_stats(&stats);
+        result.insert(
+            "active_connections".to_string(),
+            self.active_connections.load(Ordering::Relaxed).to_string(),
+        );
+        result
     }
 }
 
@@ -233,16 +357,24 @@ impl Forwarder for TCPForwarder {
         Err(anyhow::anyhow!("TCP转发器需要使用start_with_target方法"))
     }
 
+    // 关闭监听端口，已建立的连接继续转发直到结束
     async fn stop(&mut self) {
-        *self.running.write().await = false;
+        self.shutdown.cancel();
+        if let Some(accept_task) = self.accept_task.take() {
+            let _ = accept_task.await;
+        }
+    }
+
+    fn active_connections(&self) -> usize {
+        self.active_connections.load(Ordering::Relaxed)
     }
 
     fn is_running(&self) -> bool {
-        *self.running.blocking_read()
+        !self.shutdown.is_cancelled()
     }
 
-    fn get_stats(&self) -> HashMap<String, String> {
-        Self::get_stats(self)
+    async fn get_stats(&self) -> HashMap<String, String> {
+        Self::get_stats(self).await
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -258,17 +390,19 @@ impl Forwarder for TCPForwarder {
 // HTTP 转发器
 // ================================
 pub struct HTTPForwarder {
-    listen_addr: String,
+    listen_addrs: Vec<String>,
     name: String,
-    running: Arc<RwLock<bool>>,
+    shutdown: CancellationToken,
+    accept_tasks: Vec<JoinHandle<()>>,
 }
 
 impl HTTPForwarder {
-    pub fn new(listen_addr: &str, name: &str, _buffer_size: usize) -> Self {
+    pub fn new(listen_addrs: &[String], name: &str, _buffer_size: usize) -> Self {
         Self {
-            listen_addr: listen_addr.to_string(),
+            listen_addrs: listen_addrs.to_vec(),
             name: name.to_string(),
-            running: Arc::new(RwLock::new(false)),
+            shutdown: stopped_token(),
+            accept_tasks: Vec::new(),
         }
     }
 
@@ -333,50 +467,59 @@ impl HTTPForwarder {
 #[async_trait]
 impl Forwarder for HTTPForwarder {
     async fn start(&mut self) -> Result<()> {
-        *self.running.write().await = true;
-
-        let listener = match TcpListener::bind(&self.listen_addr).await {
-            Ok(listener) => {
-                info!("HTTP监听器绑定到: {}", self.listen_addr);
-                listener
-            }
-            Err(e) => {
-                return Err(anyhow::anyhow!(
-                    "HTTP监听器绑定失败 {}: {}",
-                    self.listen_addr,
-                    e
-                ));
+        // 先绑定所有地址，任一失败时不启动
+        let mut listeners = Vec::new();
+        for listen_addr in &self.listen_addrs {
+            match TcpListener::bind(listen_addr).await {
+                Ok(listener) => {
+                    info!("HTTP监听器绑定到: {listen_addr}");
+                    listeners.push(listener);
+                }
+                Err(e) => {
+                    return Err(anyhow::anyhow!("HTTP监听器绑定失败 {}: {}", listen_addr, e));
+                }
             }
-        };
-        let running = self.running.clone();
+        }
+
+        self.shutdown = CancellationToken::new();
         let name = self.name.clone();
 
-        tokio::spawn(async move {
-            while *running.read().await {
-                match listener.accept().await {
-                    Ok((stream, _)) => {
-                        tokio::spawn(async move {
-                            let _ = Self::handle_http_redirect(stream).await;
-                        });
+        for listener in listeners {
+            let shutdown = self.shutdown.clone();
+            self.accept_tasks.push(tokio::spawn(async move {
+                loop {
+                    let accepted = tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        accepted = listener.accept() => accepted,
+                    };
+                    match accepted {
+                        Ok((stream, _)) => {
+                            tokio::spawn(async move {
+                                let _ = Self::handle_http_redirect(stream).await;
+                            });
+                        }
+                        Err(_) => break,
                     }
-                    Err(_) => break,
                 }
-            }
-        });
+            }));
+        }
 
         info!("HTTP转发器启动成功: {name}");
         Ok(())
     }
 
     async fn stop(&mut self) {
-        *self.running.write().await = false;
+        self.shutdown.cancel();
+        for accept_task in self.accept_tasks.drain(..) {
+            let _ = accept_task.await;
+        }
     }
 
     fn is_running(&self) -> bool {
-        *self.running.blocking_read()
+        !self.shutdown.is_cancelled()
     }
 
-    fn get_stats(&self) -> HashMap<String, String> {
+    async fn get_stats(&self) -> HashMap<String, String> {
         let mut stats = HashMap::new();
         stats.insert("name".to_string(), self.name.clone());
         stats.insert("type".to_string(), "HTTP Redirect".to_string());
@@ -402,8 +545,11 @@ pub struct UDPForwarder {
     buffer_size: usize,
     target_addr: Arc<RwLock<String>>,
     stats: Arc<RwLock<ConnectionStats>>,
-    running: Arc<RwLock<bool>>,
+    shutdown: CancellationToken, // 停止时取消转发循环、清理任务和所有会话的回程任务
+    tasks: Vec<JoinHandle<()>>,
     sessions: Arc<RwLock<HashMap<std::net::SocketAddr, UdpSession>>>,
+    common_manager: Option<CommonManager>,
+    rule_name: String,
 }
 
 // UDP会话结构
@@ -411,6 +557,8 @@ struct UdpSession {
     upstream: Option<Arc<UdpSocket>>,
     target: std::net::SocketAddr,
     last_seen: std::time::Instant,
+    return_task: Option<JoinHandle<()>>, // 回程任务，持有监听socket
+    _connection_guard: Option<ConnectionGuard>, // 会话计入目标活跃连接数
 }
 
 impl UdpSession {
@@ -419,6 +567,17 @@ impl UdpSession {
             upstream: None,
             target: "0.0.0.0:0".parse().unwrap(),
             last_seen: std::time::Instant::now(),
+            return_task: None,
+            _connection_guard: None,
+        }
+    }
+}
+
+// 会话过期或切换目标时结束旧的回程任务
+impl Drop for UdpSession {
+    fn drop(&mut self) {
+        if let Some(return_task) = self.return_task.take() {
+            return_task.abort();
         }
     }
 }
@@ -431,14 +590,31 @@ impl UDPForwarder {
             buffer_size,
             target_addr: Arc::new(RwLock::new(String::new())),
             stats: Arc::new(RwLock::new(ConnectionStats::default())),
-            running: Arc::new(RwLock::new(false)),
+            shutdown: stopped_token(),
+            tasks: Vec::new(),
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            common_manager: None,
+            rule_name: name.to_string(),
         }
     }
 
+    // 设置公共管理器后，每个会话按规则的负载均衡策略选择目标
+    pub fn set_common_manager(&mut self, common_manager: CommonManager, rule_name: &str) {
+        self.common_manager = Some(common_manager);
+        self.rule_name = rule_name.to_string();
+    }
+
+    // 同一规则多个监听地址的转发器共用统计
+    pub fn share_stats(&mut self, stats: Arc<RwLock<ConnectionStats>>) {
+        self.stats = stats;
+    }
+
+    pub async fn session_count(&self) -> usize {
+        self.sessions.read().await.len()
+    }
+
     pub async fn start_with_target(&mut self, target: &str) -> Result<()> {
         *self.target_addr.write().await = target.to_string();
-        *self.running.write().await = true;
 
         let socket = match UdpSocket::bind(&self.listen_addr).await {
             Ok(socket) => {
@@ -455,36 +631,41 @@ impl UDPForwarder {
         };
 
         // 启动主转发循环
+        self.shutdown = CancellationToken::new();
         let stats = self.stats.clone();
-        let running = self.running.clone();
+        let shutdown = self.shutdown.clone();
         let target_addr = self.target_addr.clone();
         let sessions = self.sessions.clone();
         let buffer_size = self.buffer_size;
         let name = self.name.clone();
+        let common_manager = self.common_manager.clone();
+        let lb_rule_name = self.rule_name.clone();
 
-        tokio::spawn(async move {
+        self.tasks.push(tokio::spawn(async move {
             Self::udp_forward_loop(
                 socket,
                 buffer_size,
                 name,
                 stats,
-                running,
+                shutdown,
                 target_addr,
                 sessions,
+                common_manager,
+                lb_rule_name,
             )
             .await;
-        });
+        }));
 
         // 启动会话清理任务
         let sessions_cleanup = self.sessions.clone();
-        let running_cleanup = self.running.clone();
-        tokio::spawn(async move {
+        let shutdown_cleanup = self.shutdown.clone();
+        self.tasks.push(tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(30));
             loop {
-                if !*running_cleanup.read().await {
-                    break;
+                tokio::select! {
+                    _ = shutdown_cleanup.cancelled() => break,
+                    _ = interval.tick() => {}
                 }
-                interval.tick().await;
 
                 let now = std::time::Instant::now();
                 let mut to_remove = Vec::new();
@@ -503,40 +684,64 @@ impl UDPForwarder {
                     }
                 }
             }
-        });
+        }));
 
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn udp_forward_loop(
         socket: UdpSocket,
         buffer_size: usize,
         _name: String,
         stats: Arc<RwLock<ConnectionStats>>,
-        running: Arc<RwLock<bool>>,
+        shutdown: CancellationToken,
         target_addr: Arc<RwLock<String>>,
         sessions: Arc<RwLock<HashMap<std::net::SocketAddr, UdpSession>>>,
+        common_manager: Option<CommonManager>,
+        rule_name: String,
     ) {
         let mut buffer = vec![0u8; buffer_size];
         let socket = Arc::new(socket);
 
         loop {
-            if !*running.read().await {
-                break;
-            }
+            let received = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                received = socket.recv_from(&mut buffer) => received,
+            };
 
-            match socket.recv_from(&mut buffer).await {
+            match received {
                 Ok((len, client_addr)) => {
-                    stats.write().await.add_bytes_received(len as u64);
-
-                    let target_addr_str = target_addr.read().await.clone();
+                    // 按负载均衡策略选择会话目标（会话目标健康时保持不变）
+                    let selected = match &common_manager {
+                        Some(manager) => {
+                            let current = sessions
+                                .read()
+                                .await
+                                .get(&client_addr)
+                                .filter(|sess| sess.upstream.is_some())
+                                .map(|sess| sess.target);
+                            manager
+                                .select_session_target(&rule_name, client_addr, current)
+                                .await
+                                .ok()
+                        }
+                        None => None,
+                    };
 
-                    // 解析已解析的目标地址字符串（来自CommonManager的DNS解析结果）
-                    let target = match target_addr_str.parse::<std::net::SocketAddr>() {
-                        Ok(addr) => addr,
-                        Err(e) => {
-                            warn!("UDP目标地址解析失败: {} - {}", target_addr_str, e);
-                            continue;
+                    let target = match selected {
+                        Some(addr) => addr,
+                        None => {
+                            let target_addr_str = target_addr.read().await.clone();
+
+                            // 解析已解析的目标地址字符串（来自CommonManager的DNS解析结果）
+                            match target_addr_str.parse::<std::net::SocketAddr>() {
+                                Ok(addr) => addr,
+                                Err(e) => {
+                                    warn!("UDP目标地址解析失败: {} - {}", target_addr_str, e);
+                                    continue;
+                                }
+                            }
                         }
                     };
 
@@ -548,7 +753,12 @@ impl UDPForwarder {
 
                     // 如果没有上游socket或目标变化，重新连接
                     if entry.upstream.is_none() || entry.target != target {
-                        if let Ok(upstream) = UdpSocket::bind("0.0.0.0:0").await {
+                        let bind_addr = if target.is_ipv6() {
+                            "[::]:0"
+                        } else {
+                            "0.0.0.0:0"
+                        };
+                        if let Ok(upstream) = UdpSocket::bind(bind_addr).await {
                             if upstream.connect(target).await.is_ok() {
                                 let upstream = Arc::new(upstream);
 
@@ -556,7 +766,7 @@ impl UDPForwarder {
                                 let upstream_reader = upstream.clone();
                                 let socket_clone = socket.clone();
                                 let stats_clone = stats.clone();
-                                tokio::spawn(async move {
+                                let return_task = tokio::spawn(async move {
                                     let mut resp_buf = vec![0u8; 4096];
                                     while let Ok(resp_len) =
                                         upstream_reader.recv(&mut resp_buf).await
@@ -568,13 +778,20 @@ impl UDPForwarder {
                                             stats_clone
                                                 .write()
                                                 .await
-                                                .add_bytes_sent(resp_len as u64);
+                                                .add_bytes_received(resp_len as u64);
                                         }
                                     }
                                 });
 
+                                if let Some(old_task) = entry.return_task.replace(return_task) {
+                                    old_task.abort();
+                                }
                                 entry.upstream = Some(upstream);
                                 entry.target = target;
+                                stats.write().await.increment_connections();
+                                entry._connection_guard = common_manager
+                                    .as_ref()
+                                    .map(|manager| manager.track_connection(target));
                             }
                         }
                     }
@@ -601,9 +818,14 @@ impl UDPForwarder {
         Ok(())
     }
 
-    pub fn get_stats(&self) -> HashMap<String, String> {
-        let stats = self.stats.blocking_read();
-        get_stats_with_target(&stats, &self.target_addr.blocking_read())
+    pub async fn get_stats(&self) -> HashMap<String, String> {
+        let stats = self.stats.read().await;
+        let mut result = get_stats_with_target(&stats, &self.target_addr.read().await);
+        result.insert(
+            "sessions".to_string(),
+            self.sessions.read().await.len().to_string(),
+        );
+        result
     }
 }
 
@@ -613,16 +835,31 @@ impl Forwarder for UDPForwarder {
         Err(anyhow::anyhow!("UDP转发器需要使用start_with_target方法"))
     }
 
+    // 结束所有任务并等待其退出，返回时监听端口已释放，可以立即重新绑定
     async fn stop(&mut self) {
-        *self.running.write().await = false;
+        self.shutdown.cancel();
+        for task in self.tasks.drain(..) {
+            let _ = task.await;
+        }
+        let return_tasks: Vec<JoinHandle<()>> = self
+            .sessions
+            .write()
+            .await
+            .drain()
+            .filter_map(|(_, mut session)| session.return_task.take())
+            .collect();
+        for return_task in return_tasks {
+            return_task.abort();
+            let _ = return_task.await;
+        }
     }
 
     fn is_running(&self) -> bool {
-        *self.running.blocking_read()
+        !self.shutdown.is_cancelled()
     }
 
-    fn get_stats(&self) -> HashMap<String, String> {
-        Self::get_stats(self)
+    async fn get_stats(&self) -> HashMap<String, String> {
+        Self::get_stats(self).await
     }
 
     fn as_any(&self) -> &dyn std::any::Any {
@@ -639,26 +876,33 @@ impl Forwarder for UDPForwarder {
 // ================================
 pub struct UnifiedForwarder {
     rule: ForwardRule,
-    listen_addr: String,
+    listen_addrs: Vec<String>,
     target_addr: String,
-    tcp_forwarder: Option<TCPForwarder>,
+    tcp_forwarders: Vec<TCPForwarder>, // 每个监听地址一个
     http_forwarder: Option<HTTPForwarder>,
-    udp_forwarder: Option<UDPForwarder>,
+    udp_forwarders: Vec<UDPForwarder>, // 每个监听地址一个
     running: Arc<RwLock<bool>>,
     last_update: Arc<RwLock<Instant>>,
+    common_manager: CommonManager,
 }
 
 impl UnifiedForwarder {
-    pub fn new_with_target(rule: &ForwardRule, listen_addr: &str, target_addr: &str) -> Self {
+    pub fn new_with_target(
+        rule: &ForwardRule,
+        listen_addrs: &[String],
+        target_addr: &str,
+        common_manager: CommonManager,
+    ) -> Self {
         Self {
             rule: rule.clone(),
-            listen_addr: listen_addr.to_string(),
+            listen_addrs: listen_addrs.to_vec(),
             target_addr: target_addr.to_string(),
-            tcp_forwarder: None,
+            tcp_forwarders: Vec::new(),
             http_forwarder: None,
-            udp_forwarder: None,
+            udp_forwarders: Vec::new(),
             running: Arc::new(RwLock::new(false)),
             last_update: Arc::new(RwLock::new(Instant::now())),
+            common_manager,
         }
     }
 
@@ -668,59 +912,65 @@ impl UnifiedForwarder {
             *self.last_update.write().await = Instant::now();
 
             // 更新各转发器的目标地址
-            if let Some(ref mut tcp) = self.tcp_forwarder {
+            for tcp in &mut self.tcp_forwarders {
                 tcp.update_target(new_target).await?;
             }
-            if let Some(ref mut udp) = self.udp_forwarder {
+            for udp in &mut self.udp_forwarders {
                 udp.update_target(new_target).await?;
             }
         }
         Ok(())
     }
-}
 
-#[async_trait]
-impl Forwarder for UnifiedForwarder {
-    async fn start(&mut self) -> Result<()> {
-        *self.running.write().await = true;
+    // 热重载：监听配置不变时只替换规则配置（目标列表、动态更新参数）
+    pub fn update_rule(&mut self, rule: &ForwardRule) {
+        self.rule = rule.clone();
+    }
 
-        // 使用规则的 get_protocols() 方法获取协议列表
-        let protocols = self.rule.get_protocols();
+    async fn start_listeners(&mut self) -> Result<()> {
+        let buffer_size = self.rule.get_effective_buffer_size(8192);
 
-        for protocol in &protocols {
+        // 使用规则的 get_protocols() 方法获取协议列表
+        for protocol in self.rule.get_protocols() {
             match protocol.as_str() {
-                "tcp" => {
-                    if self.tcp_forwarder.is_none() {
+                "tcp" if self.tcp_forwarders.is_empty() => {
+                    let stats = Arc::new(RwLock::new(ConnectionStats::default()));
+                    for listen_addr in &self.listen_addrs {
                         let mut tcp_forwarder = TCPForwarder::new(
-                            &self.listen_addr,
+                            listen_addr,
                             &format!("{}_TCP", self.rule.name),
-                            self.rule.get_effective_buffer_size(8192),
+                            buffer_size,
                         );
+                        tcp_forwarder.share_stats(stats.clone());
+                        tcp_forwarder
+                            .set_common_manager(self.common_manager.clone(), &self.rule.name);
                         tcp_forwarder.start_with_target(&self.target_addr).await?;
-                        self.tcp_forwarder = Some(tcp_forwarder);
+                        self.tcp_forwarders.push(tcp_forwarder);
                     }
                 }
-                "udp" => {
-                    if self.udp_forwarder.is_none() {
+                "udp" if self.udp_forwarders.is_empty() => {
+                    let stats = Arc::new(RwLock::new(ConnectionStats::default()));
+                    for listen_addr in &self.listen_addrs {
                         let mut udp_forwarder = UDPForwarder::new(
-                            &self.listen_addr,
+                            listen_addr,
                             &format!("{}_UDP", self.rule.name),
-                            self.rule.get_effective_buffer_size(8192),
+                            buffer_size,
                         );
+                        udp_forwarder.share_stats(stats.clone());
+                        udp_forwarder
+                            .set_common_manager(self.common_manager.clone(), &self.rule.name);
                         udp_forwarder.start_with_target(&self.target_addr).await?;
-                        self.udp_forwarder = Some(udp_forwarder);
+                        self.udp_forwarders.push(udp_forwarder);
                     }
                 }
-                "http" => {
-                    if self.http_forwarder.is_none() {
-                        let mut http_forwarder = HTTPForwarder::new(
-                            &self.listen_addr,
-                            &format!("{}_HTTP", self.rule.name),
-                            self.rule.get_effective_buffer_size(8192),
-                        );
-                        http_forwarder.start().await?;
-                        self.http_forwarder = Some(http_forwarder);
-                    }
+                "http" if self.http_forwarder.is_none() => {
+                    let mut http_forwarder = HTTPForwarder::new(
+                        &self.listen_addrs,
+                        &format!("{}_HTTP", self.rule.name),
+                        buffer_size,
+                    );
+                    http_forwarder.start().await?;
+                    self.http_forwarder = Some(http_forwarder);
                 }
                 _ => {}
             }
@@ -728,14 +978,28 @@ impl Forwarder for UnifiedForwarder {
 
         Ok(())
     }
+}
+
+#[async_trait]
+impl Forwarder for UnifiedForwarder {
+    async fn start(&mut self) -> Result<()> {
+        *self.running.write().await = true;
+
+        // 任一地址启动失败时停止已启动的监听器，避免端口残留
+        if let Err(e) = self.start_listeners().await {
+            self.stop().await;
+            return Err(e);
+        }
+        Ok(())
+    }
 
     async fn stop(&mut self) {
         *self.running.write().await = false;
 
-        if let Some(ref mut tcp) = self.tcp_forwarder {
+        for tcp in &mut self.tcp_forwarders {
             tcp.stop().await;
         }
-        if let Some(ref mut udp) = self.udp_forwarder {
+        for udp in &mut self.udp_forwarders {
             udp.stop().await;
         }
         if let Some(ref mut http) = self.http_forwarder {
@@ -743,11 +1007,18 @@ impl Forwarder for UnifiedForwarder {
         }
     }
 
+    fn active_connections(&self) -> usize {
+        self.tcp_forwarders
+            .iter()
+            .map(|tcp| tcp.active_connections())
+            .sum()
+    }
+
     fn is_running(&self) -> bool {
         *self.running.blocking_read()
     }
 
-    fn get_stats(&self) -> HashMap<String, String> {
+    async fn get_stats(&self) -> HashMap<String, String> {
         let mut stats = HashMap::new();
         stats.insert("rule_name".to_string(), self.rule.name.clone());
         stats.insert("target_addr".to_string(), self.target_addr.clone());
@@ -759,20 +1030,30 @@ impl Forwarder for UnifiedForwarder {
             "tcp".to_string()
         };
         stats.insert("protocols".to_string(), protocols_str);
-        stats.insert("running".to_string(), self.is_running().to_string());
+        stats.insert("running".to_string(), self.running.read().await.to_string());
 
-        if let Some(ref tcp) = self.tcp_forwarder {
-            let tcp_stats = tcp.get_stats();
+        // 同协议的转发器共用统计，只有连接数和会话数需要累加
+        if let Some(tcp) = self.tcp_forwarders.first() {
+            let tcp_stats = tcp.get_stats().await;
             for (k, v) in tcp_stats {
                 stats.insert(format!("tcp_{k}"), v);
             }
+            stats.insert(
+                "tcp_active_connections".to_string(),
+                self.active_connections().to_string(),
+            );
         }
 
-        if let Some(ref udp) = self.udp_forwarder {
-            let udp_stats = udp.get_stats();
+        if let Some(udp) = self.udp_forwarders.first() {
+            let udp_stats = udp.get_stats().await;
             for (k, v) in udp_stats {
                 stats.insert(format!("udp_{k}"), v);
             }
+            let mut sessions = 0;
+            for udp in &self.udp_forwarders {
+                sessions += udp.session_count().await;
+            }
+            stats.insert("udp_sessions".to_string(), sessions.to_string());
         }
 
         stats
@@ -790,10 +1071,15 @@ impl Forwarder for UnifiedForwarder {
 // ================================
 // 智能转发器管理器
 // ================================
+// 优雅关闭时检查剩余连接数的间隔
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub type ForwarderMap = Arc<RwLock<HashMap<String, Box<dyn Forwarder + Send + Sync>>>>;
+
 pub struct SmartForwarder {
     config: Config,
     common_manager: CommonManager,
-    forwarders: Arc<RwLock<HashMap<String, Box<dyn Forwarder + Send + Sync>>>>,
+    forwarders: ForwarderMap,
     dynamic_update_started: Arc<RwLock<bool>>,
     firewall_scheduler: Option<Arc<Mutex<FirewallScheduler>>>,
 }
@@ -853,12 +1139,11 @@ impl SmartForwarder {
         let mut success_count = 0;
         let is_kernel_mode = self.firewall_scheduler.is_some();
 
-        // 检查是否需要自动启用HTTP跳转服务
-        let has_443 = rules.iter().any(|r| r.listen_port == 443);
-        let has_80 = rules.iter().any(|r| r.listen_port == 80);
+        // 检查是否需要自动启用HTTP跳转服务（配置了443但没有配置80）
+        let auto_http = self.needs_auto_http_redirect();
 
         // 如果配置了443但没有配置80，自动启用HTTP跳转
-        if has_443 && !has_80 {
+        if auto_http {
             if let Err(e) = self.start_auto_http_redirect().await {
                 warn!("自动HTTP跳转服务启动失败: {e}");
             } else {
@@ -886,9 +1171,9 @@ impl SmartForwarder {
         }
 
         // 计算实际启动的规则数量，区分配置规则和自动服务
-        let configured_rules_started = success_count - if has_443 && !has_80 { 1 } else { 0 };
+        let configured_rules_started = success_count - if auto_http { 1 } else { 0 };
 
-        if has_443 && !has_80 && success_count > configured_rules_started {
+        if auto_http && success_count > configured_rules_started {
             info!(
                 "启动完成: {} 个规则可用 (配置 {} 个规则 + 自动HTTP跳转服务)",
                 success_count, configured_rules_started
@@ -913,19 +1198,118 @@ impl SmartForwarder {
         Ok(())
     }
 
-    async fn start_auto_http_redirect(&mut self) -> Result<()> {
-        let listen_addr = format!("{}:80", self.config.network.first());
+    // 热重载配置：只启停变化的规则，其余规则原地更新目标，不中断现有连接
+    pub async fn reload(&mut self, new_config: Config) -> Result<()> {
+        if self.config.admin != new_config.admin {
+            warn!("⚠️  管理接口配置变化需要重启进程生效");
+        }
+
+        let diff = self.config.diff_rules(&new_config);
+        if diff.is_empty() {
+            info!("配置未变化，跳过重载");
+            return Ok(());
+        }
+
+        info!(
+            "开始重载配置: 新增 {} 个，删除 {} 个，重启 {} 个，更新 {} 个规则",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.restarted.len(),
+            diff.updated.len()
+        );
+        if diff.settings_changed {
+            info!("全局设置（动态更新/DNS/hosts/drain_timeout）已变化，随本次重载生效");
+        }
+
+        // 1. 更新公共管理器：解析新目标、健康检查、重新选择目标
+        self.common_manager
+            .apply_config(new_config.clone(), &diff)
+            .await?;
+
+        if let Some(scheduler_arc) = &self.firewall_scheduler {
+            // 2. 内核态：只删除/创建变化的防火墙规则
+            let mut scheduler = scheduler_arc.lock().await;
+            scheduler.apply_config(new_config.clone(), &diff).await?;
+            self.config = new_config;
+        } else {
+            // 2. 用户态：停止已删除和需要重启的转发器
+            {
+                let mut forwarders = self.forwarders.write().await;
+                for rule in diff.removed.iter().chain(diff.restarted.iter()) {
+                    if let Some(mut forwarder) = forwarders.remove(&rule.name) {
+                        info!("停止转发器: {}", rule.name);
+                        forwarder.stop().await;
+                    }
+                }
+
+                // 3. 仅目标变化的规则原地更新，现有连接不受影响
+                for rule in &diff.updated {
+                    if let Some(forwarder) = forwarders.get_mut(&rule.name) {
+                        if let Some(unified) =
+                            forwarder.as_any_mut().downcast_mut::<UnifiedForwarder>()
+                        {
+                            unified.update_rule(rule);
+                            if let Ok(best_target) =
+                                self.common_manager.get_best_target(&rule.name).await
+                            {
+                                unified.update_target(&best_target.to_string()).await?;
+                            }
+                        }
+                    }
+                }
+            }
 
-        // 检查80端口是否被占用
-        if tokio::net::TcpListener::bind(&listen_addr).await.is_err() {
-            warn!("端口80被占用，无法启动自动HTTP跳转服务");
-            return Ok(()); // 不返回错误，只是跳过
+            // 4. 启动新增和需要重启的转发器
+            self.config = new_config;
+            for rule in diff.added.iter().chain(diff.restarted.iter()) {
+                if let Err(e) = self.start_forwarder(rule).await {
+                    error!("规则 {} 启动失败: {}", rule.name, e);
+                }
+            }
         }
 
+        // 5. 根据新配置启停自动HTTP跳转服务
+        self.sync_auto_http_redirect().await;
+
+        info!("✅ 配置重载完成");
+        Ok(())
+    }
+
+    // 配置了443但没有配置80时需要自动HTTP跳转服务
+    fn needs_auto_http_redirect(&self) -> bool {
+        let has_443 = self.config.rules.iter().any(|r| r.listen_port == 443);
+        let has_80 = self.config.rules.iter().any(|r| r.listen_port == 80);
+        has_443 && !has_80
+    }
+
+    async fn sync_auto_http_redirect(&mut self) {
+        let running = self.forwarders.read().await.contains_key("AutoHTTP");
+        let needed = self.needs_auto_http_redirect();
+
+        if needed && !running {
+            if let Err(e) = self.start_auto_http_redirect().await {
+                warn!("自动HTTP跳转服务启动失败: {e}");
+            }
+        } else if !needed && running {
+            if let Some(mut forwarder) = self.forwarders.write().await.remove("AutoHTTP") {
+                forwarder.stop().await;
+                info!("自动HTTP跳转服务已停止");
+            }
+        }
+    }
+
+    async fn start_auto_http_redirect(&mut self) -> Result<()> {
+        let listen_addrs = self.config.network.socket_addrs(80);
+
         info!("检测到HTTPS配置但无HTTP配置，自动启用HTTP跳转服务");
 
-        let mut http_forwarder = HTTPForwarder::new(&listen_addr, "AutoHTTP", 4096);
-        http_forwarder.start().await?;
+        let mut http_forwarder = HTTPForwarder::new(&listen_addrs, "AutoHTTP", 4096);
+        if let Err(e) = http_forwarder.start().await {
+            // 80端口被占用时不返回错误，只是跳过
+            http_forwarder.stop().await;
+            warn!("端口80被占用，无法启动自动HTTP跳转服务: {e}");
+            return Ok(());
+        }
 
         // 将HTTP转发器添加到管理列表中
         self.forwarders
@@ -938,7 +1322,7 @@ impl SmartForwarder {
     }
 
     async fn start_forwarder(&mut self, rule: &ForwardRule) -> Result<()> {
-        let listen_addr = rule.get_listen_addr(&self.config.network.first());
+        let listen_addrs = self.config.network.socket_addrs(rule.listen_port);
 
         // 获取最佳目标
         if let Ok(best_target) = self.common_manager.get_best_target(&rule.name).await {
@@ -946,12 +1330,18 @@ impl SmartForwarder {
 
             info!(
                 "规则 {} 启动: {} -> {}",
-                rule.name, listen_addr, target_addr
+                rule.name,
+                listen_addrs.join(", "),
+                target_addr
             );
 
             // 创建统一转发器
-            let mut unified_forwarder =
-                UnifiedForwarder::new_with_target(rule, &listen_addr, &target_addr);
+            let mut unified_forwarder = UnifiedForwarder::new_with_target(
+                rule,
+                &listen_addrs,
+                &target_addr,
+                self.common_manager.clone(),
+            );
             match unified_forwarder.start().await {
                 Ok(_) => {
                     self.forwarders
@@ -974,7 +1364,6 @@ impl SmartForwarder {
     async fn start_dynamic_update_task(&self) {
         let forwarders = self.forwarders.clone();
         let common_manager = self.common_manager.clone();
-        let rules = self.config.rules.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(15));
@@ -983,19 +1372,15 @@ impl SmartForwarder {
                 interval.tick().await;
 
                 // 内核态转发现在使用立即回调机制，不需要定期同步
-                // 只更新用户态转发器（如果存在）
-                for rule in &rules {
-                    if let Ok(best_target) = common_manager.get_best_target(&rule.name).await {
-                        let target_addr = best_target.to_string();
-
-                        let mut forwarders_guard = forwarders.write().await;
-                        if let Some(forwarder) = forwarders_guard.get_mut(&rule.name) {
-                            if let Some(unified) =
-                                forwarder.as_any_mut().downcast_mut::<UnifiedForwarder>()
-                            {
-                                if let Err(e) = unified.update_target(&target_addr).await {
-                                    error!("规则 {} 更新目标失败: {}", rule.name, e);
-                                }
+                // 只更新用户态转发器（如果存在），规则列表以当前运行的转发器为准（支持热重载）
+                let mut forwarders_guard = forwarders.write().await;
+                for (rule_name, forwarder) in forwarders_guard.iter_mut() {
+                    if let Some(unified) = forwarder.as_any_mut().downcast_mut::<UnifiedForwarder>()
+                    {
+                        if let Ok(best_target) = common_manager.get_best_target(rule_name).await {
+                            let target_addr = best_target.to_string();
+                            if let Err(e) = unified.update_target(&target_addr).await {
+                                error!("规则 {} 更新目标失败: {}", rule_name, e);
                             }
                         }
                     }
@@ -1004,6 +1389,7 @@ impl SmartForwarder {
         });
     }
 
+    // 关闭所有监听端口并清理内核态转发规则，已建立的TCP连接由drain等待结束
     pub async fn stop(&mut self) {
         // 停止用户态转发器
         let mut forwarders = self.forwarders.write().await;
@@ -1011,9 +1397,9 @@ impl SmartForwarder {
             info!("停止转发器: {name}");
             forwarder.stop().await;
         }
-        forwarders.clear();
+        drop(forwarders);
 
-        // 清理内核态转发规则
+        // 清理内核态转发规则（先于等待连接结束，确保强制退出时也已清理）
         if let Some(scheduler_arc) = &self.firewall_scheduler {
             let mut scheduler = scheduler_arc.lock().await;
             if let Err(e) = scheduler.clear_all().await {
@@ -1024,15 +1410,130 @@ impl SmartForwarder {
         }
     }
 
-    #[allow(dead_code)]
-    pub async fn get_stats(&self) -> HashMap<String, HashMap<String, String>> {
+    // 等待现有TCP连接结束，超过drain_timeout后放弃等待
+    pub async fn drain(&self) {
+        let timeout = Duration::from_secs(self.config.get_drain_timeout());
+        let deadline = Instant::now() + timeout;
+        let mut logged = false;
+        loop {
+            let active: usize = self
+                .forwarders
+                .read()
+                .await
+                .values()
+                .map(|forwarder| forwarder.active_connections())
+                .sum();
+            if active == 0 {
+                break;
+            }
+            if Instant::now() >= deadline {
+                warn!("等待超时，强制关闭{}个未结束的连接", active);
+                break;
+            }
+            if !logged {
+                info!("等待{}个连接结束 (最多{}秒)...", active, timeout.as_secs());
+                logged = true;
+            }
+            tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        }
+        self.forwarders.write().await.clear();
+    }
+
+    // 转发器列表句柄，供管理接口读取统计信息
+    pub fn forwarders_handle(&self) -> ForwarderMap {
+        self.forwarders.clone()
+    }
+
+    pub fn common_manager(&self) -> CommonManager {
+        self.common_manager.clone()
+    }
+
+    pub async fn get_stats(forwarders: &ForwarderMap) -> HashMap<String, HashMap<String, String>> {
         let mut all_stats = HashMap::new();
-        let forwarders = self.forwarders.read().await;
+        let forwarders = forwarders.read().await;
 
         for (name, forwarder) in forwarders.iter() {
-            all_stats.insert(name.clone(), forwarder.get_stats());
+            all_stats.insert(name.clone(), forwarder.get_stats().await);
         }
 
         all_stats
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn free_addr() -> String {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        // 同一端口的TCP也需要空闲
+        std::net::TcpListener::bind(&addr).unwrap();
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_stop_releases_listeners() {
+        let listen = free_addr();
+        let target = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap().to_string();
+
+        let mut tcp = TCPForwarder::new(&listen, "test_TCP", 1024);
+        tcp.start_with_target(&target_addr).await.unwrap();
+        let mut udp = UDPForwarder::new(&listen, "test_UDP", 1024);
+        udp.start_with_target(&target_addr).await.unwrap();
+        assert!(tcp.is_running() && udp.is_running());
+
+        // 建立一个UDP会话，回程任务持有监听socket
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"ping", &listen).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (n, upstream) = target.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"ping");
+        assert_eq!(udp.sessions.read().await.len(), 1);
+
+        tcp.stop().await;
+        udp.stop().await;
+        assert!(!tcp.is_running() && !udp.is_running());
+        assert!(udp.sessions.read().await.is_empty());
+
+        // 停止后端口立即释放，可以重新绑定
+        TcpListener::bind(&listen).await.unwrap();
+        UdpSocket::bind(&listen).await.unwrap();
+
+        // 旧会话的回程任务已结束，不再转发响应
+        target.send_to(b"pong", upstream).await.unwrap();
+        let reply =
+            tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buf)).await;
+        assert!(reply.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_udp_ipv6_target() {
+        // IPv4监听转发到IPv6目标，上游socket需要按目标地址族绑定
+        let listen = free_addr();
+        let target = UdpSocket::bind("[::1]:0").await.unwrap();
+        let target_addr = target.local_addr().unwrap().to_string();
+
+        let mut udp = UDPForwarder::new(&listen, "test_UDP6", 1024);
+        udp.start_with_target(&target_addr).await.unwrap();
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.send_to(b"ping", &listen).await.unwrap();
+        let mut buf = [0u8; 16];
+        let (n, upstream) =
+            tokio::time::timeout(Duration::from_secs(2), target.recv_from(&mut buf))
+                .await
+                .expect("IPv6目标未收到数据")
+                .unwrap();
+        assert_eq!(&buf[..n], b"ping");
+
+        target.send_to(b"pong", upstream).await.unwrap();
+        let (n, _) = tokio::time::timeout(Duration::from_secs(2), client.recv_from(&mut buf))
+            .await
+            .expect("客户端未收到回复")
+            .unwrap();
+        assert_eq!(&buf[..n], b"pong");
+        udp.stop().await;
+    }
+}
@@ -0,0 +1,445 @@
+// 健康检查 - TCP连接、HTTP(S)请求、TLS握手和UDP探测
+use anyhow::Result;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_rustls::rustls;
+use tokio_rustls::TlsConnector;
+
+use crate::config::{HealthCheckConfig, HealthCheckType};
+
+// 响应最多读取64KB，足够判断状态码和内容
+const MAX_RESPONSE_SIZE: usize = 64 * 1024;
+
+// 对已解析的目标地址执行健康检查，成功时返回耗时
+pub async fn check_target(
+    addr: SocketAddr,
+    target: &str,
+    check: &HealthCheckConfig,
+    timeout: Duration,
+) -> Result<Duration> {
+    let start = Instant::now();
+    // Host头默认使用配置中的目标地址（域名:端口）
+    // SRV目标没有固定主机名，未配置host时使用解析出的地址
+    let addr_host = addr.to_string();
+    let default_host = if target.starts_with("srv://") {
+        &addr_host
+    } else {
+        target
+    };
+    let host = check.host.as_deref().unwrap_or(default_host);
+    let timeout = check.timeout.map(Duration::from_secs).unwrap_or(timeout);
+
+    let result = tokio::time::timeout(timeout, async {
+        let connect = || async {
+            TcpStream::connect(addr)
+                .await
+                .map_err(|e| anyhow::anyhow!("连接失败 {}: {}", target, e))
+        };
+
+        match check.get_type() {
+            HealthCheckType::Udp | HealthCheckType::Dns | HealthCheckType::Stun => {
+                udp_probe(addr, check).await
+            }
+            HealthCheckType::Tcp => connect().await.map(|_| ()),
+            HealthCheckType::Http => http_check(connect().await?, host, check).await,
+            HealthCheckType::Https => {
+                let stream = tls_handshake(connect().await?, host, check).await?;
+                http_check(stream, host, check).await
+            }
+            HealthCheckType::Tls => tls_handshake(connect().await?, host, check)
+                .await
+                .map(|_| ()),
+        }
+    })
+    .await;
+
+    match result {
+        Ok(Ok(())) => Ok(start.elapsed()),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(anyhow::anyhow!(
+            "{}检查超时: {}",
+            check.get_type().as_str(),
+            target
+        )),
+    }
+}
+
+async fn http_check<S>(mut stream: S, host: &str, check: &HealthCheckConfig) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = format!(
+        "{} {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         User-Agent: smart-forward/{}\r\n\
+         Accept: */*\r\n\
+         Connection: close\r\n\
+         \r\n",
+        check.get_method(),
+        check.get_path(),
+        host,
+        env!("CARGO_PKG_VERSION")
+    );
+    stream
+        .write_all(request.as_bytes())
+        .await
+        .map_err(|e| anyhow::anyhow!("发送HTTP请求失败: {}", e))?;
+
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let n = match stream.read(&mut buffer).await {
+            Ok(n) => n,
+            // 部分服务器关闭TLS连接时不发送close_notify，已收到的内容仍然有效
+            Err(_) if !response.is_empty() => break,
+            Err(e) => anyhow::bail!("读取HTTP响应失败: {}", e),
+        };
+        if n == 0 {
+            break;
+        }
+        response.extend_from_slice(&buffer[..n]);
+
+        if response.len() >= MAX_RESPONSE_SIZE {
+            break;
+        }
+        // 不检查响应内容时，收到状态行即可判断
+        if check.body_contains.is_none() && response.windows(2).any(|w| w == b"\r\n") {
+            break;
+        }
+    }
+
+    evaluate_response(&response, check)
+}
+
+// 检查HTTP响应的状态码和内容
+fn evaluate_response(response: &[u8], check: &HealthCheckConfig) -> Result<()> {
+    let response = String::from_utf8_lossy(response);
+    let status_line = response.lines().next().unwrap_or("");
+
+    let mut parts = status_line.split_whitespace();
+    let status = match (parts.next(), parts.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => code.parse::<u16>().ok(),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow::anyhow!("无效的HTTP响应: {}", status_line))?;
+
+    let (min, max) = check.get_status_range()?;
+    if status < min || status > max {
+        anyhow::bail!("HTTP状态码 {} 不在期望范围 {}-{}", status, min, max);
+    }
+
+    if let Some(expected) = &check.body_contains {
+        let body = response.split_once("\r\n\r\n").map_or("", |(_, body)| body);
+        if !body.contains(expected.as_str()) {
+            anyhow::bail!("HTTP响应内容不包含: {}", expected);
+        }
+    }
+
+    Ok(())
+}
+
+// UDP探测：发送探测包，等待回复并校验
+async fn udp_probe(addr: SocketAddr, check: &HealthCheckConfig) -> Result<()> {
+    let bind_addr = if addr.is_ipv4() {
+        "0.0.0.0:0"
+    } else {
+        "[::]:0"
+    };
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(addr).await?;
+
+    let probe = UdpProbe::new(check)?;
+    socket
+        .send(&probe.request)
+        .await
+        .map_err(|e| anyhow::anyhow!("发送UDP探测失败 {}: {}", addr, e))?;
+
+    let mut buffer = vec![0u8; 4096];
+    let n = socket
+        .recv(&mut buffer)
+        .await
+        .map_err(|e| anyhow::anyhow!("UDP探测无回复 {}: {}", addr, e))?;
+
+    probe.verify(&buffer[..n], check)
+}
+
+// UDP探测请求及预设协议的回复校验
+struct UdpProbe {
+    check_type: HealthCheckType,
+    request: Vec<u8>,
+    id: Vec<u8>, // DNS事务ID / STUN事务ID
+}
+
+impl UdpProbe {
+    fn new(check: &HealthCheckConfig) -> Result<Self> {
+        let check_type = check.get_type();
+        let (request, id) = match check_type {
+            HealthCheckType::Dns => {
+                let id = random_bytes(2);
+                let name = check.dns_query.as_deref().unwrap_or(".");
+                (dns_query(&id, name)?, id)
+            }
+            HealthCheckType::Stun => {
+                let id = random_bytes(12);
+                (stun_binding_request(&id), id)
+            }
+            _ => (check.get_payload()?.unwrap_or_default(), Vec::new()),
+        };
+        Ok(Self {
+            check_type,
+            request,
+            id,
+        })
+    }
+
+    fn verify(&self, reply: &[u8], check: &HealthCheckConfig) -> Result<()> {
+        match self.check_type {
+            // 事务ID一致且QR位为1即为DNS响应（NXDOMAIN/REFUSED也说明服务在线）
+            HealthCheckType::Dns
+                if reply.len() < 12 || reply[..2] != self.id[..] || reply[2] & 0x80 == 0 =>
+            {
+                anyhow::bail!("无效的DNS响应");
+            }
+            // 绑定成功响应 0x0101 + magic cookie + 事务ID
+            HealthCheckType::Stun
+                if reply.len() < 20
+                    || reply[..2] != [0x01, 0x01]
+                    || reply[4..8] != STUN_MAGIC_COOKIE
+                    || reply[8..20] != self.id[..] =>
+            {
+                anyhow::bail!("无效的STUN绑定响应");
+            }
+            _ => {}
+        }
+
+        if let Some(prefix) = check.get_expect_prefix()? {
+            if !reply.starts_with(&prefix) {
+                anyhow::bail!("UDP回复不匹配期望前缀");
+            }
+        }
+        if let Some(regex) = check.get_expect_regex()? {
+            if !regex.is_match(reply) {
+                anyhow::bail!("UDP回复不匹配期望正则: {}", regex.as_str());
+            }
+        }
+        Ok(())
+    }
+}
+
+const STUN_MAGIC_COOKIE: [u8; 4] = [0x21, 0x12, 0xa4, 0x42];
+
+// DNS查询：递归查询，根域名查NS记录，其他域名查A记录
+fn dns_query(id: &[u8], name: &str) -> Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(32);
+    query.extend_from_slice(id);
+    query.extend_from_slice(&[0x01, 0x00]); // 标志：RD
+    query.extend_from_slice(&[0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]); // QDCOUNT=1
+
+    let name = name.trim_end_matches('.');
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        if label.len() > 63 {
+            anyhow::bail!("无效的DNS查询域名: {}", name);
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+
+    let qtype: u8 = if name.is_empty() { 2 } else { 1 }; // NS / A
+    query.extend_from_slice(&[0x00, qtype, 0x00, 0x01]); // QCLASS=IN
+    Ok(query)
+}
+
+// STUN绑定请求（RFC 5389），无属性
+fn stun_binding_request(id: &[u8]) -> Vec<u8> {
+    let mut request = Vec::with_capacity(20);
+    request.extend_from_slice(&[0x00, 0x01, 0x00, 0x00]); // Binding Request，长度0
+    request.extend_from_slice(&STUN_MAGIC_COOKIE);
+    request.extend_from_slice(id);
+    request
+}
+
+// 随机事务ID
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len);
+    while bytes.len() < len {
+        let value = RandomState::new().build_hasher().finish();
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    bytes.truncate(len);
+    bytes
+}
+
+async fn tls_handshake(
+    stream: TcpStream,
+    host: &str,
+    check: &HealthCheckConfig,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    let server_name = rustls::ServerName::try_from(strip_port(host))
+        .map_err(|_| anyhow::anyhow!("无效的TLS服务器名称: {}", host))?;
+
+    let connector = TlsConnector::from(tls_config(check.get_tls_verify()));
+    connector
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| anyhow::anyhow!("TLS握手失败 {}: {}", host, e))
+}
+
+// TLS客户端配置（校验/不校验证书两种，首次使用时创建）
+fn tls_config(verify: bool) -> Arc<rustls::ClientConfig> {
+    static VERIFIED: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    static UNVERIFIED: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+
+    let cell = if verify { &VERIFIED } else { &UNVERIFIED };
+    cell.get_or_init(|| {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+
+        let mut config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        if !verify {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoCertificateVerification));
+        }
+        Arc::new(config)
+    })
+    .clone()
+}
+
+// 去掉Host中的端口，得到TLS服务器名称
+fn strip_port(host: &str) -> &str {
+    if let Some(rest) = host.strip_prefix('[') {
+        // [IPv6]:port
+        return rest.split(']').next().unwrap_or(rest);
+    }
+    match host.rsplit_once(':') {
+        // 只有一个冒号时才是 域名:端口，多个冒号是不带括号的IPv6地址
+        Some((name, _)) if !name.contains(':') => name,
+        _ => host,
+    }
+}
+
+// 不校验证书：后端通常使用自签名证书或通过IP访问
+struct NoCertificateVerification;
+
+impl rustls::client::ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+    fn check_config(yaml: &str) -> HealthCheckConfig {
+        serde_yml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_evaluate_response() {
+        let check = check_config("type: http\nbody_contains: ok");
+        assert!(evaluate_response(b"HTTP/1.1 200 OK\r\n\r\nstatus: ok", &check).is_ok());
+        assert!(evaluate_response(b"HTTP/1.1 200 OK\r\n\r\nstatus: down", &check).is_err());
+        assert!(evaluate_response(b"HTTP/1.1 502 Bad Gateway\r\n\r\nok", &check).is_err());
+        assert!(evaluate_response(b"SSH-2.0-OpenSSH\r\n", &check).is_err());
+
+        assert_eq!(strip_port("example.com:443"), "example.com");
+        assert_eq!(strip_port("[::1]:443"), "::1");
+        assert_eq!(strip_port("::1"), "::1");
+    }
+
+    #[test]
+    fn test_udp_probe_presets() {
+        let check = check_config("type: dns\ndns_query: example.com");
+        let probe = UdpProbe::new(&check).unwrap();
+        assert_eq!(probe.request.len(), 12 + 13 + 4);
+        assert_eq!(&probe.request[12..25], b"\x07example\x03com\x00");
+
+        // DNS响应：相同事务ID且设置QR位
+        let mut reply = probe.request.clone();
+        reply[2] |= 0x80;
+        assert!(probe.verify(&reply, &check).is_ok());
+        assert!(probe.verify(&probe.request, &check).is_err());
+
+        let check = check_config("type: stun");
+        let probe = UdpProbe::new(&check).unwrap();
+        let mut reply = probe.request.clone();
+        reply[..2].copy_from_slice(&[0x01, 0x01]);
+        assert!(probe.verify(&reply, &check).is_ok());
+        reply[19] ^= 0xff;
+        assert!(probe.verify(&reply, &check).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_udp_check() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 1024];
+            while let Ok((n, peer)) = server.recv_from(&mut buffer).await {
+                let reply = [b"pong ".as_slice(), &buffer[..n]].concat();
+                let _ = server.send_to(&reply, peer).await;
+            }
+        });
+
+        let target = addr.to_string();
+        let check = check_config("type: udp\npayload: ping\nexpect: pong");
+        assert!(check_target(addr, &target, &check, HEALTH_CHECK_TIMEOUT)
+            .await
+            .is_ok());
+        let check = check_config("type: udp\npayload: ping\nexpect_regex: \"^pong [0-9]+$\"");
+        assert!(check_target(addr, &target, &check, HEALTH_CHECK_TIMEOUT)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_check() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for status in ["200 OK", "502 Bad Gateway"] {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buffer = [0u8; 1024];
+                let _ = stream.read(&mut buffer).await;
+                let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n");
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+
+        let check = check_config("type: http\npath: /health");
+        let target = addr.to_string();
+        assert!(check_target(addr, &target, &check, HEALTH_CHECK_TIMEOUT)
+            .await
+            .is_ok());
+        assert!(check_target(addr, &target, &check, HEALTH_CHECK_TIMEOUT)
+            .await
+            .is_err());
+    }
+}
@@ -1,14 +1,23 @@
+mod admin;
 mod common;
 mod config;
+mod dns;
 mod firewall;
 mod forwarder;
+mod health;
+mod metrics;
+#[cfg(target_os = "linux")]
+mod nft_netlink;
+mod reload;
+mod txt;
 mod utils;
 
 use anyhow::Result;
 use clap::Parser;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
 use std::path::PathBuf;
 
+use crate::admin::AdminServer;
 use crate::common::CommonManager;
 use crate::config::Config;
 use crate::firewall::{detect_firewall_backend, FirewallBackend, FirewallScheduler};
@@ -56,7 +65,7 @@ struct Args {
     #[arg(long)]
     user_mode: bool,
 
-    /// 防火墙后端选择 (nftables/iptables/auto)
+    /// 防火墙后端选择 (nftables/nftables-netlink/iptables/auto)
     #[arg(long, default_value = "auto")]
     firewall_backend: String,
 }
@@ -153,6 +162,7 @@ async fn main() -> Result<()> {
     // 解析防火墙后端
     let firewall_backend = match args.firewall_backend.as_str() {
         "nftables" => FirewallBackend::Nftables,
+        "nftables-netlink" => FirewallBackend::NftablesNetlink,
         "iptables" => FirewallBackend::Iptables,
         "auto" => detect_firewall_backend(),
         _ => {
@@ -190,12 +200,43 @@ async fn main() -> Result<()> {
             "  连接超时: {}秒",
             global_dynamic_config.get_connection_timeout()
         );
+        println!(
+            "  故障判定: 连续失败{}次异常, 连续成功{}次恢复, 回切延迟{}秒",
+            global_dynamic_config.get_fall(),
+            global_dynamic_config.get_rise(),
+            global_dynamic_config.get_failback_delay()
+        );
+
+        let dns_config = config.get_dns_config();
+        println!("\n📋 DNS配置:");
+        println!("  模式: {}", dns_config.get_mode().as_str());
+        if !dns_config.servers.is_empty() {
+            println!("  DNS服务器: {:?}", dns_config.servers);
+        }
+        println!(
+            "  缓存TTL: {}~{}秒, IP偏好: {}",
+            dns_config.get_min_ttl(),
+            dns_config.get_max_ttl(),
+            dns_config.get_ip_preference().as_str()
+        );
+        for (name, profile) in config.dns_profiles.iter().flatten() {
+            println!("  DNS配置 {}: {:?}", name, profile.servers);
+        }
+        for (hostname, addrs) in config.get_hosts() {
+            println!("  hosts: {} -> {:?}", hostname, addrs);
+        }
 
         // 验证规则配置
         println!("\n📋 转发规则配置:");
         for (i, rule) in config.rules.iter().enumerate() {
             println!("  规则 {}: {}", i + 1, rule.name);
             println!("    监听端口: {}", rule.listen_port);
+            if let Some(profile) = &rule.dns_profile {
+                println!("    DNS配置: {}", profile);
+            }
+            if !rule.get_masquerade() {
+                println!("    内核态SNAT: 关闭");
+            }
 
             // 显示协议信息
             let protocols = rule.get_protocols();
@@ -210,6 +251,16 @@ async fn main() -> Result<()> {
                 rule.get_effective_buffer_size(8192)
             );
             println!("    目标地址: {:?}", rule.targets);
+            println!(
+                "    负载均衡策略: {}",
+                rule.strategy.as_deref().unwrap_or("priority")
+            );
+            if let Some(weights) = &rule.weights {
+                println!("    目标权重: {weights:?}");
+            }
+            if let Some(health_check) = &rule.health_check {
+                println!("    健康检查: {}", health_check.get_type().as_str());
+            }
 
             // 验证规则级别的动态更新配置
             let rule_dynamic_config = rule.get_dynamic_update_config(&global_dynamic_config);
@@ -222,14 +273,27 @@ async fn main() -> Result<()> {
                 "      连接超时: {}秒",
                 rule_dynamic_config.get_connection_timeout()
             );
+            println!(
+                "      故障判定: 连续失败{}次异常, 连续成功{}次恢复, 回切延迟{}秒",
+                rule_dynamic_config.get_fall(),
+                rule_dynamic_config.get_rise(),
+                rule_dynamic_config.get_failback_delay()
+            );
             println!();
         }
 
+        if let Some(admin) = &config.admin {
+            println!("📊 管理接口: http://{}/api/rules", admin.listen);
+        }
+
         println!("✅ 配置验证完成");
         println!("🎉 所有配置项验证通过！");
         return Ok(());
     }
 
+    // 尽早接管关闭信号：启动期间（健康检查、DNS解析、绑定端口）收到信号时也要先清理内核态规则再退出
+    let mut shutdown_signals = ShutdownSignals::new();
+
     // 创建公共管理器
     let common_manager = CommonManager::new(config.clone());
     common_manager.initialize().await?;
@@ -252,7 +316,11 @@ async fn main() -> Result<()> {
                 common_manager.clone(),
             )
             .await?;
-            scheduler.initialize().await?;
+            if let Err(e) = scheduler.initialize().await {
+                // 初始化可能已经创建了部分规则
+                let _ = scheduler.clear_all().await;
+                return Err(e);
+            }
             info!("✅ 内核态转发启用成功，防火墙后端: {:?}", firewall_backend);
             Some(scheduler)
         } else if !args.validate_config {
@@ -274,6 +342,9 @@ async fn main() -> Result<()> {
                         Some(scheduler)
                     }
                     Err(e) => {
+                        // 回退前清理初始化过程中可能已经创建的规则
+                        let _ = scheduler.clear_all().await;
+
                         // 检查是否是权限相关错误
                         let error_msg = format!("{}", e);
                         if error_msg.contains("权限不足")
@@ -319,22 +390,141 @@ async fn main() -> Result<()> {
     };
 
     // 创建智能转发器
+    let admin_config = config.admin.clone();
     let mut forwarder = SmartForwarder::new(config, common_manager, firewall_scheduler);
 
-    // 初始化转发器
-    forwarder.initialize().await?;
+    // 初始化并启动转发器；启动失败或期间收到关闭信号时先停止转发器并清理内核态规则
+    let started = tokio::select! {
+        result = async {
+            forwarder.initialize().await?;
+            forwarder.start().await
+        } => result.map(|_| true),
+        signal = shutdown_signals.recv() => {
+            info!("启动期间收到{signal}信号，正在停止...");
+            Ok(false)
+        }
+    };
+    match started {
+        Ok(true) => {}
+        Ok(false) => {
+            forwarder.stop().await;
+            info!("智能转发器已停止");
+            return Ok(());
+        }
+        Err(e) => {
+            forwarder.stop().await;
+            return Err(e);
+        }
+    }
+
+    // 启动管理接口（可选）
+    if let Some(admin_config) = &admin_config {
+        let admin_server = AdminServer::new(
+            admin_config,
+            forwarder.common_manager(),
+            forwarder.forwarders_handle(),
+        );
+        if let Err(e) = admin_server.start().await {
+            error!("管理接口启动失败: {e}");
+        }
+    }
 
-    // 启动转发器
-    forwarder.start().await?;
+    // 监听配置变化（SIGHUP或文件修改），热重载时不中断现有连接
+    let mut reload_rx = reload::watch_config(args.config.clone());
 
     // 等待关闭信号
-    tokio::signal::ctrl_c().await?;
-
-    info!("收到关闭信号，正在停止...");
+    loop {
+        tokio::select! {
+            signal = shutdown_signals.recv() => {
+                info!("收到{signal}信号，正在停止...");
+                break;
+            }
+            Some(()) = reload_rx.recv() => {
+                // 新配置无效时保留当前运行配置
+                match Config::load_from_file(&args.config) {
+                    Ok(new_config) => {
+                        if let Err(e) = forwarder.reload(new_config).await {
+                            error!("配置重载失败: {e}");
+                        }
+                    }
+                    Err(e) => {
+                        error!("新配置验证失败，继续使用当前配置: {e}");
+                    }
+                }
+            }
+        }
+    }
 
-    // 停止转发器
+    // 立即关闭监听端口并清理防火墙规则，再等待现有连接结束；再次收到信号时不再等待
     forwarder.stop().await;
+    tokio::select! {
+        _ = forwarder.drain() => {}
+        signal = shutdown_signals.recv() => {
+            warn!("再次收到{signal}信号，不再等待连接结束");
+        }
+    }
 
     info!("智能转发器已停止");
     Ok(())
 }
+
+// 关闭信号：SIGTERM (systemctl/docker stop)、SIGINT (Ctrl+C)、SIGQUIT
+// 信号流在启动时创建一次并一直保留，热重载期间到达的信号也会被记录，不会丢失
+#[cfg(unix)]
+struct ShutdownSignals {
+    terminate: Option<tokio::signal::unix::Signal>,
+    interrupt: Option<tokio::signal::unix::Signal>,
+    quit: Option<tokio::signal::unix::Signal>,
+}
+
+#[cfg(unix)]
+impl ShutdownSignals {
+    fn new() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let listen = |kind: SignalKind| match signal(kind) {
+            Ok(stream) => Some(stream),
+            Err(e) => {
+                warn!("无法监听信号 {:?}: {e}", kind);
+                None
+            }
+        };
+        Self {
+            terminate: listen(SignalKind::terminate()),
+            interrupt: listen(SignalKind::interrupt()),
+            quit: listen(SignalKind::quit()),
+        }
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        async fn recv(stream: &mut Option<tokio::signal::unix::Signal>) {
+            match stream {
+                Some(stream) => {
+                    stream.recv().await;
+                }
+                None => std::future::pending().await,
+            }
+        }
+
+        tokio::select! {
+            _ = recv(&mut self.terminate) => "SIGTERM",
+            _ = recv(&mut self.interrupt) => "SIGINT",
+            _ = recv(&mut self.quit) => "SIGQUIT",
+        }
+    }
+}
+
+#[cfg(not(unix))]
+struct ShutdownSignals;
+
+#[cfg(not(unix))]
+impl ShutdownSignals {
+    fn new() -> Self {
+        Self
+    }
+
+    async fn recv(&mut self) -> &'static str {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
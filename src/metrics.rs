@@ -0,0 +1,259 @@
+// Prometheus指标 - 以文本格式导出转发统计和健康检查状态，由管理接口的 /metrics 提供
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::common::CommonManager;
+use crate::forwarder::{ForwarderMap, SmartForwarder};
+
+// Prometheus文本格式构建器
+struct MetricsWriter {
+    output: String,
+}
+
+impl MetricsWriter {
+    fn new() -> Self {
+        Self {
+            output: String::new(),
+        }
+    }
+
+    // 指标说明和类型，每个指标只写一次
+    fn header(&mut self, name: &str, metric_type: &str, help: &str) {
+        let _ = writeln!(self.output, "# HELP {name} {help}");
+        let _ = writeln!(self.output, "# TYPE {name} {metric_type}");
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        self.output.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                .collect();
+            let _ = write!(self.output, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.output, " {value}");
+    }
+
+    fn finish(self) -> String {
+        self.output
+    }
+}
+
+// 标签值转义：反斜杠、双引号、换行
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// 从转发器统计中读取数值（统计信息以字符串形式保存）
+fn stat_value(stats: &HashMap<String, String>, key: &str) -> Option<f64> {
+    stats.get(key).and_then(|value| value.parse().ok())
+}
+
+pub async fn render(common_manager: &CommonManager, forwarders: &ForwarderMap) -> String {
+    let mut writer = MetricsWriter::new();
+
+    // 1. 转发统计（仅用户态转发有数据）
+    let mut all_stats: Vec<_> = SmartForwarder::get_stats(forwarders)
+        .await
+        .into_iter()
+        .collect();
+    all_stats.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let counters = [
+        (
+            "smart_forward_bytes_sent_total",
+            "bytes_sent",
+            "客户端发往目标的字节数",
+        ),
+        (
+            "smart_forward_bytes_received_total",
+            "bytes_received",
+            "目标返回客户端的字节数",
+        ),
+        (
+            "smart_forward_connections_total",
+            "connections",
+            "TCP连接数/UDP会话建立次数累计",
+        ),
+    ];
+    for (metric, key, help) in counters {
+        writer.header(metric, "counter", help);
+        for (rule, stats) in &all_stats {
+            for protocol in ["tcp", "udp"] {
+                if let Some(value) = stat_value(stats, &format!("{protocol}_{key}")) {
+                    writer.sample(metric, &[("rule", rule), ("protocol", protocol)], value);
+                }
+            }
+        }
+    }
+
+    writer.header("smart_forward_udp_sessions", "gauge", "当前活跃的UDP会话数");
+    for (rule, stats) in &all_stats {
+        if let Some(value) = stat_value(stats, "udp_sessions") {
+            writer.sample("smart_forward_udp_sessions", &[("rule", rule)], value);
+        }
+    }
+
+    // 2. 目标健康状态
+    let targets = common_manager.target_snapshots();
+
+    writer.header(
+        "smart_forward_target_healthy",
+        "gauge",
+        "目标健康状态 (1=健康, 0=异常)",
+    );
+    for target in &targets {
+        let resolved = target.resolved.to_string();
+        writer.sample(
+            "smart_forward_target_healthy",
+            &[("target", &target.original), ("resolved", &resolved)],
+            if target.healthy { 1.0 } else { 0.0 },
+        );
+    }
+
+    writer.header(
+        "smart_forward_target_fail_count",
+        "gauge",
+        "目标连续健康检查失败次数",
+    );
+    for target in &targets {
+        let resolved = target.resolved.to_string();
+        writer.sample(
+            "smart_forward_target_fail_count",
+            &[("target", &target.original), ("resolved", &resolved)],
+            target.fail_count as f64,
+        );
+    }
+
+    writer.header(
+        "smart_forward_health_check_duration_seconds",
+        "gauge",
+        "最近一次健康检查耗时 (秒)",
+    );
+    for target in &targets {
+        if let Some(latency) = target.check_latency {
+            let resolved = target.resolved.to_string();
+            writer.sample(
+                "smart_forward_health_check_duration_seconds",
+                &[("target", &target.original), ("resolved", &resolved)],
+                latency.as_secs_f64(),
+            );
+        }
+    }
+
+    // 3. 规则目标切换
+    let rules = common_manager.rule_snapshots().await;
+
+    writer.header(
+        "smart_forward_target_switches_total",
+        "counter",
+        "规则目标切换次数",
+    );
+    for rule in &rules {
+        writer.sample(
+            "smart_forward_target_switches_total",
+            &[("rule", &rule.name)],
+            rule.switch_count as f64,
+        );
+    }
+
+    writer.header(
+        "smart_forward_rule_selected_target",
+        "gauge",
+        "规则当前选中的目标 (值恒为1)",
+    );
+    for rule in &rules {
+        if let Some(target) = &rule.selected_target {
+            let resolved = target.resolved.to_string();
+            writer.sample(
+                "smart_forward_rule_selected_target",
+                &[
+                    ("rule", &rule.name),
+                    ("target", &target.original),
+                    ("resolved", &resolved),
+                ],
+                1.0,
+            );
+        }
+    }
+
+    // 4. DNS解析
+    let dns = common_manager.dns_stats();
+    writer.header(
+        "smart_forward_dns_cache_hits_total",
+        "counter",
+        "DNS解析缓存命中次数",
+    );
+    writer.sample(
+        "smart_forward_dns_cache_hits_total",
+        &[],
+        dns.cache_hits as f64,
+    );
+    writer.header(
+        "smart_forward_dns_cache_misses_total",
+        "counter",
+        "DNS解析缓存未命中次数 (即上游DNS查询次数)",
+    );
+    writer.sample(
+        "smart_forward_dns_cache_misses_total",
+        &[],
+        dns.cache_misses as f64,
+    );
+    writer.header(
+        "smart_forward_dns_lookup_errors_total",
+        "counter",
+        "上游DNS查询失败次数",
+    );
+    writer.sample(
+        "smart_forward_dns_lookup_errors_total",
+        &[],
+        dns.lookup_errors as f64,
+    );
+    writer.header(
+        "smart_forward_dns_lookup_duration_seconds_total",
+        "counter",
+        "上游DNS查询累计耗时 (秒)",
+    );
+    writer.sample(
+        "smart_forward_dns_lookup_duration_seconds_total",
+        &[],
+        dns.lookup_seconds,
+    );
+    writer.header(
+        "smart_forward_dns_cache_entries",
+        "gauge",
+        "DNS解析缓存条目数",
+    );
+    writer.sample(
+        "smart_forward_dns_cache_entries",
+        &[],
+        dns.cache_entries as f64,
+    );
+
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_writer() {
+        let mut writer = MetricsWriter::new();
+        writer.header("test_total", "counter", "测试");
+        writer.sample("test_total", &[("rule", "a\"b\\c")], 3.0);
+        writer.sample("test_total", &[], 0.5);
+
+        assert_eq!(
+            writer.finish(),
+            "# HELP test_total 测试\n\
+             # TYPE test_total counter\n\
+             test_total{rule=\"a\\\"b\\\\c\"} 3\n\
+             test_total 0.5\n"
+        );
+    }
+}
@@ -0,0 +1,627 @@
+// nf_tables netlink协议 - 不依赖nft命令，直接通过netlink批量提交表、链和规则
+//
+// 一次提交的所有消息放在 BATCH_BEGIN/BATCH_END 之间，内核按单个事务处理：
+// 任一消息失败时整批回滚，原规则集保持不变
+use anyhow::Result;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// netlink / nfnetlink
+const NETLINK_NETFILTER: i32 = 12;
+const NLMSG_ERROR: u16 = 2;
+#[cfg(test)]
+const NLMSG_DONE: u16 = 3;
+const NLM_F_REQUEST: u16 = 0x1;
+const NLM_F_ACK: u16 = 0x4;
+#[cfg(test)]
+const NLM_F_DUMP: u16 = 0x300;
+const NLM_F_APPEND: u16 = 0x800;
+const NLM_F_CREATE: u16 = 0x400;
+const NLA_F_NESTED: u16 = 0x8000;
+const NFNL_MSG_BATCH_BEGIN: u16 = 0x10;
+const NFNL_MSG_BATCH_END: u16 = 0x11;
+const NFNL_SUBSYS_NFTABLES: u16 = 10;
+
+// nf_tables 消息类型
+const NFT_MSG_NEWTABLE: u16 = 0;
+const NFT_MSG_DELTABLE: u16 = 2;
+const NFT_MSG_NEWCHAIN: u16 = 3;
+const NFT_MSG_NEWRULE: u16 = 6;
+#[cfg(test)]
+const NFT_MSG_GETRULE: u16 = 7;
+
+// 协议族
+const NFPROTO_INET: u8 = 1;
+const NFPROTO_IPV4: u8 = 2;
+const NFPROTO_IPV6: u8 = 10;
+
+// 属性
+const NFTA_TABLE_NAME: u16 = 1;
+const NFTA_CHAIN_TABLE: u16 = 1;
+const NFTA_CHAIN_NAME: u16 = 3;
+const NFTA_CHAIN_HOOK: u16 = 4;
+const NFTA_CHAIN_TYPE: u16 = 7;
+const NFTA_HOOK_HOOKNUM: u16 = 1;
+const NFTA_HOOK_PRIORITY: u16 = 2;
+const NFTA_RULE_TABLE: u16 = 1;
+const NFTA_RULE_CHAIN: u16 = 2;
+const NFTA_RULE_EXPRESSIONS: u16 = 4;
+const NFTA_LIST_ELEM: u16 = 1;
+const NFTA_EXPR_NAME: u16 = 1;
+const NFTA_EXPR_DATA: u16 = 2;
+const NFTA_DATA_VALUE: u16 = 1;
+
+// 寄存器：NFT_REG_1/NFT_REG_2 各16字节，足够放IPv6地址
+const NFT_REG_1: u32 = 1;
+const NFT_REG_2: u32 = 2;
+
+const NFT_META_NFPROTO: u32 = 15;
+const NFT_META_L4PROTO: u32 = 16;
+const NFT_PAYLOAD_NETWORK_HEADER: u32 = 1;
+const NFT_PAYLOAD_TRANSPORT_HEADER: u32 = 2;
+const NFT_CMP_EQ: u32 = 0;
+const NFT_CMP_NEQ: u32 = 1;
+const NFT_CT_STATUS: u32 = 2;
+const NFT_NAT_DNAT: u32 = 1;
+const IPS_DST_NAT: u32 = 1 << 5;
+
+// 链挂载点
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    Prerouting = 0,
+    Postrouting = 4,
+}
+
+// 规则表达式，编码方式与 nft 命令生成的一致
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Meta { key: u32 },
+    Payload { base: u32, offset: u32, len: u32 },
+    Cmp { op: u32, data: Vec<u8> },
+    Ct { key: u32 },
+    Bitwise { mask: Vec<u8>, xor: Vec<u8> },
+    Immediate { dreg: u32, data: Vec<u8> },
+    Dnat { family: u8 },
+    Masq,
+}
+
+// meta nfproto ipv4/ipv6
+pub fn match_nfproto(ipv6: bool) -> Vec<Expr> {
+    let family = if ipv6 { NFPROTO_IPV6 } else { NFPROTO_IPV4 };
+    vec![
+        Expr::Meta {
+            key: NFT_META_NFPROTO,
+        },
+        cmp_eq(vec![family]),
+    ]
+}
+
+// ip daddr / ip6 daddr
+pub fn match_daddr(ip: IpAddr) -> Vec<Expr> {
+    let (offset, data) = match ip {
+        IpAddr::V4(ip) => (16, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (24, ip.octets().to_vec()),
+    };
+    vec![
+        Expr::Payload {
+            base: NFT_PAYLOAD_NETWORK_HEADER,
+            offset,
+            len: data.len() as u32,
+        },
+        cmp_eq(data),
+    ]
+}
+
+// meta l4proto + 目标端口
+pub fn match_l4_dport(l4proto: u8, port: u16) -> Vec<Expr> {
+    vec![
+        Expr::Meta {
+            key: NFT_META_L4PROTO,
+        },
+        cmp_eq(vec![l4proto]),
+        Expr::Payload {
+            base: NFT_PAYLOAD_TRANSPORT_HEADER,
+            offset: 2,
+            len: 2,
+        },
+        cmp_eq(port.to_be_bytes().to_vec()),
+    ]
+}
+
+// ct status dnat (conntrack状态是主机字节序)
+pub fn match_ct_dnat() -> Vec<Expr> {
+    vec![
+        Expr::Ct { key: NFT_CT_STATUS },
+        Expr::Bitwise {
+            mask: IPS_DST_NAT.to_ne_bytes().to_vec(),
+            xor: vec![0; 4],
+        },
+        Expr::Cmp {
+            op: NFT_CMP_NEQ,
+            data: vec![0; 4],
+        },
+    ]
+}
+
+// dnat to 目标地址:端口
+pub fn dnat(target: SocketAddr) -> Vec<Expr> {
+    let (family, addr) = match target.ip() {
+        IpAddr::V4(ip) => (NFPROTO_IPV4, ip.octets().to_vec()),
+        IpAddr::V6(ip) => (NFPROTO_IPV6, ip.octets().to_vec()),
+    };
+    vec![
+        Expr::Immediate {
+            dreg: NFT_REG_1,
+            data: addr,
+        },
+        Expr::Immediate {
+            dreg: NFT_REG_2,
+            data: target.port().to_be_bytes().to_vec(),
+        },
+        Expr::Dnat { family },
+    ]
+}
+
+pub fn masquerade() -> Vec<Expr> {
+    vec![Expr::Masq]
+}
+
+fn cmp_eq(data: Vec<u8>) -> Expr {
+    Expr::Cmp {
+        op: NFT_CMP_EQ,
+        data,
+    }
+}
+
+// 协议名转换为IP协议号
+pub fn l4proto(protocol: &str) -> Option<u8> {
+    match protocol {
+        "tcp" => Some(libc::IPPROTO_TCP as u8),
+        "udp" => Some(libc::IPPROTO_UDP as u8),
+        _ => None,
+    }
+}
+
+// netlink属性编码：长度不含填充，整体按4字节对齐
+struct Attrs {
+    buf: Vec<u8>,
+}
+
+impl Attrs {
+    fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
+
+    fn put(&mut self, kind: u16, data: &[u8]) {
+        self.buf
+            .extend_from_slice(&((4 + data.len()) as u16).to_ne_bytes());
+        self.buf.extend_from_slice(&kind.to_ne_bytes());
+        self.buf.extend_from_slice(data);
+        while !self.buf.len().is_multiple_of(4) {
+            self.buf.push(0);
+        }
+    }
+
+    fn put_str(&mut self, kind: u16, value: &str) {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        self.put(kind, &data);
+    }
+
+    // nf_tables的整数属性都是网络字节序
+    fn put_u32(&mut self, kind: u16, value: u32) {
+        self.put(kind, &value.to_be_bytes());
+    }
+
+    fn put_nested(&mut self, kind: u16, nested: Attrs) {
+        self.put(kind | NLA_F_NESTED, &nested.buf);
+    }
+
+    fn put_data(&mut self, kind: u16, value: &[u8]) {
+        let mut data = Attrs::new();
+        data.put(NFTA_DATA_VALUE, value);
+        self.put_nested(kind, data);
+    }
+}
+
+impl Expr {
+    fn name(&self) -> &'static str {
+        match self {
+            Expr::Meta { .. } => "meta",
+            Expr::Payload { .. } => "payload",
+            Expr::Cmp { .. } => "cmp",
+            Expr::Ct { .. } => "ct",
+            Expr::Bitwise { .. } => "bitwise",
+            Expr::Immediate { .. } => "immediate",
+            Expr::Dnat { .. } => "nat",
+            Expr::Masq => "masq",
+        }
+    }
+
+    // 匹配类表达式都把数据加载到 NFT_REG_1 再比较
+    fn encode(&self) -> Attrs {
+        let mut data = Attrs::new();
+        match self {
+            Expr::Meta { key } => {
+                data.put_u32(1, NFT_REG_1); // NFTA_META_DREG
+                data.put_u32(2, *key); // NFTA_META_KEY
+            }
+            Expr::Payload { base, offset, len } => {
+                data.put_u32(1, NFT_REG_1); // NFTA_PAYLOAD_DREG
+                data.put_u32(2, *base); // NFTA_PAYLOAD_BASE
+                data.put_u32(3, *offset); // NFTA_PAYLOAD_OFFSET
+                data.put_u32(4, *len); // NFTA_PAYLOAD_LEN
+            }
+            Expr::Cmp { op, data: value } => {
+                data.put_u32(1, NFT_REG_1); // NFTA_CMP_SREG
+                data.put_u32(2, *op); // NFTA_CMP_OP
+                data.put_data(3, value); // NFTA_CMP_DATA
+            }
+            Expr::Ct { key } => {
+                data.put_u32(1, NFT_REG_1); // NFTA_CT_DREG
+                data.put_u32(2, *key); // NFTA_CT_KEY
+            }
+            Expr::Bitwise { mask, xor } => {
+                data.put_u32(1, NFT_REG_1); // NFTA_BITWISE_SREG
+                data.put_u32(2, NFT_REG_1); // NFTA_BITWISE_DREG
+                data.put_u32(3, mask.len() as u32); // NFTA_BITWISE_LEN
+                data.put_data(4, mask); // NFTA_BITWISE_MASK
+                data.put_data(5, xor); // NFTA_BITWISE_XOR
+            }
+            Expr::Immediate { dreg, data: value } => {
+                data.put_u32(1, *dreg); // NFTA_IMMEDIATE_DREG
+                data.put_data(2, value); // NFTA_IMMEDIATE_DATA
+            }
+            Expr::Dnat { family } => {
+                data.put_u32(1, NFT_NAT_DNAT); // NFTA_NAT_TYPE
+                data.put_u32(2, *family as u32); // NFTA_NAT_FAMILY
+                data.put_u32(3, NFT_REG_1); // NFTA_NAT_REG_ADDR_MIN
+                data.put_u32(5, NFT_REG_2); // NFTA_NAT_REG_PROTO_MIN
+            }
+            Expr::Masq => {}
+        }
+        data
+    }
+}
+
+// 一批nf_tables消息，整批在同一个事务中生效
+pub struct Batch {
+    buf: Vec<u8>,
+    seq: u32,
+    acks: usize,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        let mut batch = Self {
+            buf: Vec::new(),
+            seq: 0,
+            acks: 0,
+        };
+        // BATCH_BEGIN的res_id是子系统号
+        batch.push(
+            NFNL_MSG_BATCH_BEGIN,
+            NLM_F_REQUEST,
+            0,
+            NFNL_SUBSYS_NFTABLES,
+            Attrs::new(),
+        );
+        batch
+    }
+
+    pub fn add_table(&mut self, table: &str) {
+        let mut attrs = Attrs::new();
+        attrs.put_str(NFTA_TABLE_NAME, table);
+        self.push_nft(NFT_MSG_NEWTABLE, NLM_F_CREATE, attrs);
+    }
+
+    pub fn del_table(&mut self, table: &str) {
+        let mut attrs = Attrs::new();
+        attrs.put_str(NFTA_TABLE_NAME, table);
+        self.push_nft(NFT_MSG_DELTABLE, 0, attrs);
+    }
+
+    pub fn add_nat_chain(&mut self, table: &str, chain: &str, hook: Hook, priority: i32) {
+        let mut hook_attrs = Attrs::new();
+        hook_attrs.put_u32(NFTA_HOOK_HOOKNUM, hook as u32);
+        hook_attrs.put_u32(NFTA_HOOK_PRIORITY, priority as u32);
+
+        let mut attrs = Attrs::new();
+        attrs.put_str(NFTA_CHAIN_TABLE, table);
+        attrs.put_str(NFTA_CHAIN_NAME, chain);
+        attrs.put_nested(NFTA_CHAIN_HOOK, hook_attrs);
+        attrs.put_str(NFTA_CHAIN_TYPE, "nat");
+        self.push_nft(NFT_MSG_NEWCHAIN, NLM_F_CREATE, attrs);
+    }
+
+    pub fn add_rule(&mut self, table: &str, chain: &str, exprs: &[Expr]) {
+        let mut list = Attrs::new();
+        for expr in exprs {
+            let mut elem = Attrs::new();
+            elem.put_str(NFTA_EXPR_NAME, expr.name());
+            let data = expr.encode();
+            if !data.buf.is_empty() {
+                elem.put_nested(NFTA_EXPR_DATA, data);
+            }
+            list.put_nested(NFTA_LIST_ELEM, elem);
+        }
+
+        let mut attrs = Attrs::new();
+        attrs.put_str(NFTA_RULE_TABLE, table);
+        attrs.put_str(NFTA_RULE_CHAIN, chain);
+        attrs.put_nested(NFTA_RULE_EXPRESSIONS, list);
+        self.push_nft(NFT_MSG_NEWRULE, NLM_F_CREATE | NLM_F_APPEND, attrs);
+    }
+
+    fn push_nft(&mut self, msg: u16, flags: u16, attrs: Attrs) {
+        self.acks += 1;
+        self.push(
+            (NFNL_SUBSYS_NFTABLES << 8) | msg,
+            NLM_F_REQUEST | NLM_F_ACK | flags,
+            NFPROTO_INET,
+            0,
+            attrs,
+        );
+    }
+
+    fn push(&mut self, kind: u16, flags: u16, family: u8, res_id: u16, attrs: Attrs) {
+        self.seq += 1;
+        push_message(
+            &mut self.buf,
+            kind,
+            flags,
+            self.seq,
+            family,
+            res_id,
+            &attrs.buf,
+        );
+    }
+
+    fn finish(mut self) -> (Vec<u8>, usize) {
+        self.push(
+            NFNL_MSG_BATCH_END,
+            NLM_F_REQUEST,
+            0,
+            NFNL_SUBSYS_NFTABLES,
+            Attrs::new(),
+        );
+        (self.buf, self.acks)
+    }
+}
+
+impl Default for Batch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// nlmsghdr + nfgenmsg + 属性
+fn push_message(
+    buf: &mut Vec<u8>,
+    kind: u16,
+    flags: u16,
+    seq: u32,
+    family: u8,
+    res_id: u16,
+    attrs: &[u8],
+) {
+    let len = 16 + 4 + attrs.len();
+    buf.extend_from_slice(&(len as u32).to_ne_bytes());
+    buf.extend_from_slice(&kind.to_ne_bytes());
+    buf.extend_from_slice(&flags.to_ne_bytes());
+    buf.extend_from_slice(&seq.to_ne_bytes());
+    buf.extend_from_slice(&0u32.to_ne_bytes());
+    buf.push(family);
+    buf.push(0); // NFNETLINK_V0
+    buf.extend_from_slice(&res_id.to_be_bytes());
+    buf.extend_from_slice(attrs);
+}
+
+struct NetlinkSocket {
+    fd: OwnedFd,
+}
+
+impl NetlinkSocket {
+    fn open() -> Result<Self> {
+        // SAFETY: socket() 只接收整数参数，不涉及内存；返回值在下面检查
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                NETLINK_NETFILTER,
+            )
+        };
+        if fd < 0 {
+            return Err(anyhow::anyhow!(
+                "创建netlink套接字失败: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        // SAFETY: fd 是上面刚创建成功的套接字，没有其他所有者，交给 OwnedFd 负责关闭
+        let socket = Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        };
+
+        // 内核没有应答时不要一直阻塞
+        let timeout = libc::timeval {
+            tv_sec: 5,
+            tv_usec: 0,
+        };
+        // SAFETY: 选项值指向栈上有效的 timeval，长度与其类型一致，调用期间一直存活
+        let ret = unsafe {
+            libc::setsockopt(
+                socket.fd.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVTIMEO,
+                &timeout as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::timeval>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(anyhow::anyhow!(
+                "设置netlink接收超时失败: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(socket)
+    }
+
+    fn send(&self, buf: &[u8]) -> Result<()> {
+        // SAFETY: 指针和长度来自同一个切片，send() 只读取这段内存
+        let sent = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                buf.as_ptr() as *const libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(anyhow::anyhow!(
+                "发送netlink消息失败: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+        Ok(())
+    }
+
+    // 读取一个数据报，返回其中每条消息的 (类型, 消息内容)
+    fn recv(&self) -> Result<Vec<(u16, Vec<u8>)>> {
+        let mut buf = vec![0u8; 65536];
+        // SAFETY: 指针和长度来自同一个可写缓冲区，recv() 最多写入 buf.len() 字节
+        let len = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if len < 0 {
+            return Err(anyhow::anyhow!(
+                "等待内核应答失败: {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        let len = len as usize;
+        while offset + 16 <= len {
+            let msg_len = u32::from_ne_bytes(buf[offset..offset + 4].try_into()?) as usize;
+            if msg_len < 16 || offset + msg_len > len {
+                break;
+            }
+            let kind = u16::from_ne_bytes(buf[offset + 4..offset + 6].try_into()?);
+            messages.push((kind, buf[offset..offset + msg_len].to_vec()));
+            offset += (msg_len + 3) & !3;
+        }
+        Ok(messages)
+    }
+}
+
+// 提交一批消息并等待每条消息的应答，任一消息出错时返回错误 (整批已被内核回滚)
+// 阻塞调用，异步代码中需要放到 spawn_blocking 里执行
+pub fn send_batch(batch: Batch) -> Result<()> {
+    let (buf, expected) = batch.finish();
+    let socket = NetlinkSocket::open()?;
+    socket.send(&buf)?;
+
+    let mut acked = 0;
+    while acked < expected {
+        for (kind, message) in socket.recv()? {
+            if kind != NLMSG_ERROR || message.len() < 20 {
+                continue;
+            }
+            let errno = i32::from_ne_bytes(message[16..20].try_into()?);
+            if errno != 0 {
+                return Err(anyhow::anyhow!(
+                    "nf_tables事务提交失败: {}",
+                    std::io::Error::from_raw_os_error(-errno)
+                ));
+            }
+            acked += 1;
+        }
+    }
+    Ok(())
+}
+
+// 统计表中的规则数，测试用于确认内核中的实际规则
+#[cfg(test)]
+pub fn count_rules(table: &str) -> Result<usize> {
+    let mut attrs = Attrs::new();
+    attrs.put_str(NFTA_RULE_TABLE, table);
+    let mut buf = Vec::new();
+    push_message(
+        &mut buf,
+        (NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_GETRULE,
+        NLM_F_REQUEST | NLM_F_DUMP,
+        1,
+        NFPROTO_INET,
+        0,
+        &attrs.buf,
+    );
+
+    let socket = NetlinkSocket::open()?;
+    socket.send(&buf)?;
+    let mut count = 0;
+    loop {
+        for (kind, message) in socket.recv()? {
+            match kind {
+                NLMSG_DONE => return Ok(count),
+                NLMSG_ERROR => {
+                    let errno = i32::from_ne_bytes(message[16..20].try_into()?);
+                    return Err(anyhow::anyhow!(
+                        "读取规则失败: {}",
+                        std::io::Error::from_raw_os_error(-errno)
+                    ));
+                }
+                _ => count += 1,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attr_encoding() {
+        let mut attrs = Attrs::new();
+        attrs.put_str(NFTA_TABLE_NAME, "nat");
+        attrs.put_u32(2, 1);
+        // 名称 "nat\0" 正好4字节，整数为网络字节序
+        assert_eq!(
+            attrs.buf,
+            vec![8, 0, 1, 0, b'n', b'a', b't', 0, 8, 0, 2, 0, 0, 0, 0, 1]
+        );
+
+        let mut attrs = Attrs::new();
+        attrs.put(1, &[0xaa]);
+        assert_eq!(attrs.buf, vec![5, 0, 1, 0, 0xaa, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_batch_messages() {
+        let mut batch = Batch::new();
+        batch.add_table("smart_forward");
+        batch.del_table("smart_forward");
+
+        let (buf, expected) = batch.finish();
+        assert_eq!(expected, 2);
+        // BATCH_BEGIN: 20字节，res_id为nftables子系统
+        assert_eq!(u32::from_ne_bytes(buf[0..4].try_into().unwrap()), 20);
+        assert_eq!(
+            u16::from_ne_bytes(buf[4..6].try_into().unwrap()),
+            NFNL_MSG_BATCH_BEGIN
+        );
+        assert_eq!(&buf[18..20], &NFNL_SUBSYS_NFTABLES.to_be_bytes());
+        // 第一条nf_tables消息
+        assert_eq!(
+            u16::from_ne_bytes(buf[24..26].try_into().unwrap()),
+            (NFNL_SUBSYS_NFTABLES << 8) | NFT_MSG_NEWTABLE
+        );
+        assert_eq!(buf[36], NFPROTO_INET);
+    }
+}
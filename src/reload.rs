@@ -0,0 +1,61 @@
+// 配置热重载 - 监听SIGHUP信号和配置文件变化
+use log::{info, warn};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc;
+
+// 配置文件变化检测间隔
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 启动配置变化监听，收到SIGHUP或配置文件修改时间变化时发送重载通知
+pub fn watch_config(path: PathBuf) -> mpsc::Receiver<()> {
+    // 容量为1：短时间内的多次变化合并为一次重载
+    let (tx, rx) = mpsc::channel(1);
+
+    // SIGHUP信号（仅Unix）
+    #[cfg(unix)]
+    {
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    warn!("无法监听SIGHUP信号: {e}");
+                    return;
+                }
+            };
+
+            while hangup.recv().await.is_some() {
+                info!("收到SIGHUP信号，准备重载配置");
+                let _ = tx.try_send(());
+            }
+        });
+    }
+
+    // 轮询配置文件修改时间，避免引入额外的文件监听依赖
+    tokio::spawn(async move {
+        let mut last_modified = modified_time(&path);
+        let mut interval = tokio::time::interval(FILE_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let current = modified_time(&path);
+            if current.is_some() && current != last_modified {
+                info!("检测到配置文件变化: {}", path.display());
+                last_modified = current;
+                let _ = tx.try_send(());
+            }
+        }
+    });
+
+    rx
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,249 @@
+// TXT记录目标格式 - 纯域名目标通过TXT记录发布转发地址
+//
+// 旧格式：整条记录就是 IP:PORT，如 "1.2.3.4:3389"
+// v=sf1格式：一条记录发布多个地址及元数据，条目之间用分号分隔：
+//   v=sf1 ttl=60; addr=1.2.3.4:3389 prio=10 weight=3; addr=[2001:db8::1]:3389 prio=20; sig=<hex>
+//   addr   - 转发地址 (必填)
+//   prio   - 优先级，越小越优先 (默认0)
+//   weight - 权重，加权轮询使用 (可选)
+//   ttl    - 解析结果缓存秒数提示 (可选)
+//   sig    - HMAC-SHA256签名 (十六进制)，必须是最后一项；签名内容为 sig 之前的部分去掉首尾空白和分号
+// 配置了 dns.txt_key 时只接受签名正确的v=sf1记录，防止伪造的TXT应答劫持流量
+use anyhow::Result;
+use ring::hmac;
+use std::net::SocketAddr;
+
+use crate::utils::decode_hex;
+
+const SF1_PREFIX: &str = "v=sf1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxtEndpoint {
+    pub addr: SocketAddr,
+    pub priority: u32,
+    pub weight: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TxtTarget {
+    pub endpoints: Vec<TxtEndpoint>, // 按优先级排序
+    pub ttl: Option<u64>,
+}
+
+// 解析域名的所有TXT记录：存在v=sf1记录时只使用v=sf1记录，否则按旧格式取第一个有效地址
+pub fn parse_txt_records(records: &[String], key: Option<&str>) -> Result<TxtTarget> {
+    let records: Vec<&str> = records
+        .iter()
+        .map(|record| record.trim_matches('"').trim())
+        .collect();
+
+    let sf1_records: Vec<&str> = records
+        .iter()
+        .copied()
+        .filter(|record| is_sf1(record))
+        .collect();
+    if sf1_records.is_empty() {
+        if key.is_some() {
+            anyhow::bail!("已配置txt_key，只接受带签名的v=sf1记录");
+        }
+        // 旧格式与之前的行为保持一致：只使用第一条有效的IP:PORT记录
+        let addr = records
+            .iter()
+            .find_map(|record| record.parse::<SocketAddr>().ok())
+            .ok_or_else(|| anyhow::anyhow!("TXT记录中没有找到有效的IP:PORT格式"))?;
+        return Ok(TxtTarget {
+            endpoints: vec![TxtEndpoint {
+                addr,
+                priority: 0,
+                weight: None,
+            }],
+            ttl: None,
+        });
+    }
+
+    // 多条v=sf1记录合并，无效的记录跳过
+    let mut target = TxtTarget::default();
+    let mut last_error = None;
+    for record in sf1_records {
+        match parse_sf1(record, key) {
+            Ok(parsed) => {
+                target.endpoints.extend(parsed.endpoints);
+                target.ttl = match (target.ttl, parsed.ttl) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                };
+            }
+            Err(e) => {
+                log::warn!("忽略无效的v=sf1记录 \"{record}\": {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    if target.endpoints.is_empty() {
+        return Err(last_error.unwrap_or_else(|| anyhow::anyhow!("v=sf1记录中没有地址")));
+    }
+    target.endpoints.sort_by_key(|endpoint| endpoint.priority);
+    Ok(target)
+}
+
+fn is_sf1(record: &str) -> bool {
+    record
+        .split(|c: char| c.is_whitespace() || c == ';')
+        .next()
+        .is_some_and(|version| version.eq_ignore_ascii_case(SF1_PREFIX))
+}
+
+fn parse_sf1(record: &str, key: Option<&str>) -> Result<TxtTarget> {
+    // 签名必须是最后一项，签名内容是它之前的部分
+    let (payload, signature) = match record.rfind("sig=") {
+        Some(index) => (
+            record[..index].trim().trim_end_matches(';').trim_end(),
+            Some(record[index + 4..].trim().trim_end_matches(';').trim()),
+        ),
+        None => (record, None),
+    };
+
+    if let Some(key) = key {
+        let signature = signature.ok_or_else(|| anyhow::anyhow!("缺少签名"))?;
+        verify_signature(payload, signature, key)?;
+    }
+
+    let mut target = TxtTarget::default();
+    let body = payload[SF1_PREFIX.len()..].trim_start();
+    for entry in body.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut addr = None;
+        let mut priority = 0;
+        let mut weight = None;
+        for token in entry.split_whitespace() {
+            let (name, value) = token
+                .split_once('=')
+                .ok_or_else(|| anyhow::anyhow!("无效的字段: {}", token))?;
+            match name {
+                "addr" => {
+                    addr = Some(
+                        value
+                            .parse::<SocketAddr>()
+                            .map_err(|_| anyhow::anyhow!("无效的地址: {}", value))?,
+                    )
+                }
+                "prio" => priority = parse_number(name, value)?,
+                "weight" => weight = Some(parse_number(name, value)?),
+                "ttl" => target.ttl = Some(parse_number(name, value)? as u64),
+                // 未知字段忽略，便于以后扩展
+                _ => {}
+            }
+        }
+
+        match addr {
+            Some(addr) => target.endpoints.push(TxtEndpoint {
+                addr,
+                priority,
+                weight,
+            }),
+            None if priority != 0 || weight.is_some() => {
+                anyhow::bail!("条目缺少addr: {}", entry)
+            }
+            None => {}
+        }
+    }
+
+    if target.endpoints.is_empty() {
+        anyhow::bail!("没有addr条目");
+    }
+    Ok(target)
+}
+
+fn parse_number(name: &str, value: &str) -> Result<u32> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("无效的{}: {}", name, value))
+}
+
+fn verify_signature(payload: &str, signature: &str, key: &str) -> Result<()> {
+    let signature = decode_hex(signature)?;
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    hmac::verify(&key, payload.as_bytes(), &signature).map_err(|_| anyhow::anyhow!("签名校验失败"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sign(payload: &str, key: &str) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        hmac::sign(&key, payload.as_bytes())
+            .as_ref()
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+
+    fn records(records: &[&str]) -> Vec<String> {
+        records.iter().map(|r| r.to_string()).collect()
+    }
+
+    #[test]
+    fn test_legacy_format() {
+        let target = parse_txt_records(
+            &records(&["hello", "\"1.2.3.4:3389\"", "5.6.7.8:3389"]),
+            None,
+        )
+        .unwrap();
+        // 多条旧格式记录只取第一条有效地址
+        assert_eq!(target.endpoints.len(), 1);
+        assert_eq!(target.endpoints[0].addr, "1.2.3.4:3389".parse().unwrap());
+        assert!(parse_txt_records(&records(&["hello"]), None).is_err());
+    }
+
+    #[test]
+    fn test_sf1_format() {
+        let target = parse_txt_records(
+            &records(&[
+                "1.1.1.1:80",
+                "v=sf1 ttl=60; addr=[2001:db8::1]:3389 prio=20; addr=1.2.3.4:3389 prio=10 weight=3",
+            ]),
+            None,
+        )
+        .unwrap();
+
+        // 存在v=sf1记录时忽略旧格式记录，按优先级排序
+        assert_eq!(target.ttl, Some(60));
+        assert_eq!(
+            target.endpoints,
+            vec![
+                TxtEndpoint {
+                    addr: "1.2.3.4:3389".parse().unwrap(),
+                    priority: 10,
+                    weight: Some(3),
+                },
+                TxtEndpoint {
+                    addr: "[2001:db8::1]:3389".parse().unwrap(),
+                    priority: 20,
+                    weight: None,
+                },
+            ]
+        );
+
+        assert!(parse_txt_records(&records(&["v=sf1 addr=1.2.3.4"]), None).is_err());
+        assert!(parse_txt_records(&records(&["v=sf1 prio=1"]), None).is_err());
+    }
+
+    #[test]
+    fn test_sf1_signature() {
+        let key = "secret";
+        let payload = "v=sf1 addr=1.2.3.4:3389 prio=10; addr=5.6.7.8:3389 prio=20";
+        let signed = format!("{payload}; sig={}", sign(payload, key));
+
+        let target = parse_txt_records(&records(&[&signed]), Some(key)).unwrap();
+        assert_eq!(target.endpoints.len(), 2);
+        // 未配置密钥时忽略签名
+        assert!(parse_txt_records(&records(&[&signed]), None).is_ok());
+
+        // 篡改地址、缺少签名、旧格式记录都被拒绝
+        let tampered = signed.replace("5.6.7.8", "6.6.6.6");
+        assert!(parse_txt_records(&records(&[&tampered]), Some(key)).is_err());
+        assert!(parse_txt_records(&records(&[payload]), Some(key)).is_err());
+        assert!(parse_txt_records(&records(&["1.2.3.4:3389"]), Some(key)).is_err());
+        assert!(parse_txt_records(&records(&[&signed]), Some("other")).is_err());
+    }
+}
@@ -1,13 +1,11 @@
 use anyhow::Result;
-use hickory_resolver::{
-    config::{ResolverConfig, ResolverOpts},
-    Resolver,
-};
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
 
-use crate::config::DnsConfig;
+use crate::config::IpPreference;
+use crate::dns::DnsResolver;
+use crate::txt::parse_txt_records;
 
 pub struct ConnectionStats {
     pub bytes_sent: u64,
@@ -45,111 +43,182 @@ impl ConnectionStats {
     }
 }
 
-pub async fn resolve_target(target: &str, dns_config: &DnsConfig) -> Result<SocketAddr> {
+// 目标解析出的单个地址，SRV和v=sf1 TXT目标附带记录中的权重
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedAddr {
+    pub addr: SocketAddr,
+    pub weight: Option<u32>,
+}
+
+impl From<SocketAddr> for ResolvedAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self { addr, weight: None }
+    }
+}
+
+// 目标中需要DNS解析的名称（缓存失效使用），IP:PORT目标返回None
+pub fn target_hostname(target: &str) -> Option<&str> {
+    if target.parse::<SocketAddr>().is_ok() {
+        return None;
+    }
+    match target.strip_prefix("srv://") {
+        Some(name) => Some(name),
+        None => target.split(':').next(),
+    }
+}
+
+// 解析目标地址，域名目标返回所有A/AAAA记录（按IP版本偏好过滤和排序）
+// 支持的格式：IP:PORT、域名:PORT (A/AAAA)、域名 (TXT记录，IP:PORT或v=sf1格式)、srv://服务名 (SRV记录)
+// 配置了hosts的域名直接使用固定地址，不查询DNS
+pub async fn resolve_target(target: &str, resolver: &DnsResolver) -> Result<Vec<ResolvedAddr>> {
     // 1. 尝试直接解析为SocketAddr (IP:PORT格式)
     if let Ok(addr) = target.parse::<SocketAddr>() {
-        return Ok(addr);
+        return Ok(vec![addr.into()]);
+    }
+
+    // 2. SRV记录：按优先级展开为多个地址
+    if let Some(name) = target.strip_prefix("srv://") {
+        return resolve_srv(name, resolver).await;
     }
 
-    // 2. 解析 hostname:port 格式
+    // 3. 纯域名 - 解析TXT记录获取地址（旧格式IP:PORT或v=sf1格式）
     let parts: Vec<&str> = target.split(':').collect();
-    match parts.len() {
-        1 => {
-            // 纯域名 - 解析TXT记录获取IP:PORT
-            let hostname = parts[0];
-            resolve_with_dns(hostname, None, dns_config).await
-        }
-        2 => {
-            // 域名:port 格式 - 解析A/AAAA记录，然后拼接端口
-            let hostname = parts[0];
-            let port: u16 = parts[1]
-                .parse()
-                .map_err(|e| anyhow::anyhow!("无效的端口号 {}: {}", parts[1], e))?;
-            resolve_with_dns(hostname, Some(port), dns_config).await
-        }
-        _ => {
-            anyhow::bail!("无效的目标格式: {}", target);
+    if parts.len() == 1 {
+        // hosts中固定了IP:PORT的纯域名不再查询TXT记录
+        if let Some(pinned) = resolver.pinned(parts[0]) {
+            let addrs: Vec<SocketAddr> = pinned.iter().filter_map(|a| a.parse().ok()).collect();
+            if !addrs.is_empty() {
+                return Ok(addrs.into_iter().map(ResolvedAddr::from).collect());
+            }
         }
+        return resolve_txt(parts[0], resolver).await;
     }
+
+    // 4. 域名:port 格式 - 解析A/AAAA记录，然后拼接端口
+    if parts.len() != 2 {
+        anyhow::bail!("无效的目标格式: {}", target);
+    }
+    let hostname = parts[0];
+    let port: u16 = parts[1]
+        .parse()
+        .map_err(|e| anyhow::anyhow!("无效的端口号 {}: {}", parts[1], e))?;
+    let ips = resolver.lookup_ip(hostname).await?;
+    let addrs = ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect();
+
+    let addrs = apply_ip_preference(addrs, resolver.ip_preference());
+    if addrs.is_empty() {
+        anyhow::bail!("没有找到可用的IP地址: {}", target);
+    }
+    Ok(addrs.into_iter().map(ResolvedAddr::from).collect())
 }
 
-// 统一的DNS解析函数 - 支持A/AAAA和TXT记录
-async fn resolve_with_dns(
-    hostname: &str,
-    port: Option<u16>,
-    dns_config: &DnsConfig,
-) -> Result<SocketAddr> {
-    let hostname = hostname.to_string();
-    let dns_config = dns_config.clone();
-
-    let result = tokio::task::spawn_blocking(move || {
-        // 创建DNS解析器
-        let mut config = ResolverConfig::new();
-        for dns_server in &dns_config.servers {
-            if let Ok(addr) = dns_server.parse::<SocketAddr>() {
-                config.add_name_server(hickory_resolver::config::NameServerConfig::new(
-                    addr,
-                    hickory_resolver::config::Protocol::Udp,
-                ));
-            }
+// TXT目标：按记录中的优先级排列，v=sf1记录的权重用于加权轮询
+// 只按IP版本过滤，不按prefer-v4/v6重新排序，记录发布者指定的优先级优先
+async fn resolve_txt(hostname: &str, resolver: &DnsResolver) -> Result<Vec<ResolvedAddr>> {
+    let records = resolver
+        .lookup_txt(hostname)
+        .await
+        .map_err(|e| anyhow::anyhow!("TXT记录查询失败 {}: {}", hostname, e))?;
+    let parsed = parse_txt_records(&records, resolver.txt_key().as_deref())
+        .map_err(|e| anyhow::anyhow!("TXT记录无效 {}: {}", hostname, e))?;
+    if let Some(ttl) = parsed.ttl {
+        resolver.apply_txt_ttl_hint(hostname, ttl);
+    }
+
+    let preference = resolver.ip_preference();
+    let mut result: Vec<ResolvedAddr> = Vec::new();
+    for endpoint in parsed.endpoints {
+        let allowed = match preference {
+            IpPreference::Ipv4Only => endpoint.addr.is_ipv4(),
+            IpPreference::Ipv6Only => endpoint.addr.is_ipv6(),
+            IpPreference::PreferV4 | IpPreference::PreferV6 => true,
+        };
+        if allowed && !result.iter().any(|r| r.addr == endpoint.addr) {
+            result.push(ResolvedAddr {
+                addr: endpoint.addr,
+                weight: endpoint.weight,
+            });
         }
+    }
+
+    if result.is_empty() {
+        anyhow::bail!("TXT记录中没有可用的地址: {}", hostname);
+    }
+    Ok(result)
+}
+
+// SRV目标：按优先级从小到大、同优先级按权重从大到小排列，每条记录的主机名再解析A/AAAA
+async fn resolve_srv(name: &str, resolver: &DnsResolver) -> Result<Vec<ResolvedAddr>> {
+    let mut records = resolver
+        .lookup_srv(name)
+        .await
+        .map_err(|e| anyhow::anyhow!("SRV记录查询失败 {}: {}", name, e))?;
+    records.retain(|record| record.target != ".");
+    records.sort_by_key(|record| (record.priority, std::cmp::Reverse(record.weight)));
 
-        let mut opts = ResolverOpts::default();
-        opts.timeout = Duration::from_secs(dns_config.timeout.unwrap_or(2));
-        opts.attempts = dns_config.attempts.unwrap_or(2);
-        let resolver = Resolver::new(config, opts)?;
-
-        match port {
-            Some(p) => {
-                // 有端口：解析A/AAAA记录，拼接端口
-                match resolver.lookup_ip(&hostname) {
-                    Ok(response) => {
-                        if let Some(addr) = response.iter().find(|addr| addr.is_ipv4()) {
-                            Ok(SocketAddr::new(addr, p))
-                        } else if let Some(addr) = response.iter().next() {
-                            Ok(SocketAddr::new(addr, p))
-                        } else {
-                            anyhow::bail!("没有找到可用的IP地址: {}", hostname)
-                        }
-                    }
-                    Err(e) => anyhow::bail!("DNS解析失败 {}: {}", hostname, e),
-                }
+    let mut result: Vec<ResolvedAddr> = Vec::new();
+    for record in records {
+        let ips = match resolver.lookup_ip(&record.target).await {
+            Ok(ips) => ips,
+            Err(e) => {
+                log::debug!("SRV目标 {} 解析失败: {}", record.target, e);
+                continue;
             }
-            None => {
-                // 无端口：解析TXT记录获取IP:PORT
-                match resolver.txt_lookup(&hostname) {
-                    Ok(txt_response) => {
-                        for txt in txt_response.iter() {
-                            for txt_data in txt.iter() {
-                                let txt_string = String::from_utf8_lossy(txt_data);
-                                let clean_txt = txt_string.trim_matches('"').trim();
-                                if let Ok(addr) = clean_txt.parse::<SocketAddr>() {
-                                    return Ok(addr);
-                                }
-                            }
-                        }
-                        anyhow::bail!("TXT记录中没有找到有效的IP:PORT格式: {}", hostname)
-                    }
-                    Err(e) => anyhow::bail!("TXT记录查询失败 {}: {}", hostname, e),
-                }
+        };
+        let addrs = ips
+            .into_iter()
+            .map(|ip| SocketAddr::new(ip, record.port))
+            .collect();
+        for addr in apply_ip_preference(addrs, resolver.ip_preference()) {
+            if !result.iter().any(|r| r.addr == addr) {
+                result.push(ResolvedAddr {
+                    addr,
+                    weight: Some(record.weight as u32),
+                });
             }
         }
-    })
-    .await?;
+    }
 
-    result
+    if result.is_empty() {
+        anyhow::bail!("SRV记录中没有可用的目标地址: {}", name);
+    }
+    Ok(result)
 }
 
-pub async fn test_connection(target: &str, dns_config: &DnsConfig) -> Result<Duration> {
-    let addr = resolve_target(target, dns_config).await?;
-    let start = Instant::now();
+// 按IP版本偏好过滤和排序，同版本内保持DNS返回顺序并去重
+fn apply_ip_preference(addrs: Vec<SocketAddr>, preference: IpPreference) -> Vec<SocketAddr> {
+    let mut result: Vec<SocketAddr> = Vec::new();
+    for addr in addrs {
+        let allowed = match preference {
+            IpPreference::Ipv4Only => addr.is_ipv4(),
+            IpPreference::Ipv6Only => addr.is_ipv6(),
+            IpPreference::PreferV4 | IpPreference::PreferV6 => true,
+        };
+        if allowed && !result.contains(&addr) {
+            result.push(addr);
+        }
+    }
+    match preference {
+        IpPreference::PreferV4 => result.sort_by_key(|addr| addr.is_ipv6()),
+        IpPreference::PreferV6 => result.sort_by_key(|addr| addr.is_ipv4()),
+        _ => {}
+    }
+    result
+}
 
-    // 统一使用3秒超时时间，提高故障检测速度
-    match tokio::time::timeout(Duration::from_secs(3), tokio::net::TcpStream::connect(addr)).await {
-        Ok(Ok(_)) => Ok(start.elapsed()),
-        Ok(Err(e)) => Err(anyhow::anyhow!("连接失败 {}: {}", target, e)),
-        Err(_) => Err(anyhow::anyhow!("连接超时: {}", target)),
+// 解析十六进制字符串，允许空格分隔，如 "de ad be ef"
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let digits: String = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("无效的十六进制字符串: {}", hex);
     }
+    Ok((0..digits.len())
+        .step_by(2)
+        .filter_map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect())
 }
 
 // UDP连接测试函数
@@ -194,3 +263,41 @@ pub fn get_stats_with_target(
     result.insert("target_addr".to_string(), target_addr.to_string());
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_ip_preference() {
+        let addrs: Vec<SocketAddr> = [
+            "[2001:db8::1]:80",
+            "10.0.0.1:80",
+            "10.0.0.2:80",
+            "10.0.0.1:80",
+        ]
+        .iter()
+        .map(|addr| addr.parse().unwrap())
+        .collect();
+        let render = |preference| {
+            apply_ip_preference(addrs.clone(), preference)
+                .iter()
+                .map(|addr| addr.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            render(IpPreference::PreferV4),
+            ["10.0.0.1:80", "10.0.0.2:80", "[2001:db8::1]:80"]
+        );
+        assert_eq!(
+            render(IpPreference::PreferV6),
+            ["[2001:db8::1]:80", "10.0.0.1:80", "10.0.0.2:80"]
+        );
+        assert_eq!(
+            render(IpPreference::Ipv4Only),
+            ["10.0.0.1:80", "10.0.0.2:80"]
+        );
+        assert_eq!(render(IpPreference::Ipv6Only), ["[2001:db8::1]:80"]);
+    }
+}